@@ -0,0 +1,533 @@
+use std::{io::{Error, ErrorKind}, num::{NonZeroU32, NonZeroUsize}, str::FromStr};
+
+use crate::{config::Settings, remote::{Provider, RemoteUrl}, rewrite::LineEnding};
+
+pub const USAGE: &str = "\
+Usage: trunk-ghpages [OPTIONS]
+       trunk-ghpages <COMMAND> [ARGS]
+
+Commands:
+    touch-nojekyll [DIR]
+                        Only create a .nojekyll file in DIR [default: dist], without deploying
+
+Options:
+    --recursive-html    Also rewrite root-relative paths in the other *.html files of dist, like 404.html
+    -y, --yes           Force-push without asking for confirmation when the branch already exists
+    --build             Run `trunk build --release` before deploying
+    --trunk-bin <PATH>  Path of the trunk executable to use [default: trunk]
+    --git-bin <PATH>    Path of the git executable to use [default: git]
+    --repo <URL>        URL of the repo to deploy to [default: remote origin in .git/config]
+    --target <URL:BRANCH>
+                        Deploy to BRANCH of the repo at URL instead. Can be given more than once to deploy to several repos
+    --fail-fast         Stop at the first --target that fails, instead of deploying to the rest
+    --remote-url-env <VAR>
+                        Read the URL of the repo to deploy to from the environment variable VAR
+    --token <TOKEN>     Access token to push to an HTTP(S) remote with, added to the remote URL
+    --credential-helper Give --token to git with a temporary credential helper instead of adding it to the remote URL
+    --branch <NAME>     Branch to deploy to [default: gh-pages]
+    --dist <DIR>        Directory containing the built site [default: dist]
+    --archive <PATH>    Deploy the site in a .zip, .tar.gz, .tgz or .tar archive instead of dist
+    --require-existing-branch
+                        Fail instead of creating the branch when it doesn't exist on the remote yet
+    --target-subdir <DIR>
+                        Deploy into DIR of the branch, keeping the rest of the branch and its history
+    --version-dir <VERSION>
+                        Deploy into the VERSION folder of the branch, and redirect the root to the newest version
+    --mount <SUBPATH>   Deploy into SUBPATH of a <user>.github.io repo, serving the site under /SUBPATH/ instead of the repo name
+    --root-redirect     Write a root index.html to the branch redirecting to the --mount or --target-subdir folder
+    --cname <DOMAIN>    Write a CNAME file for a custom domain into the deployed site
+    --provider <NAME>   Pages provider the site is hosted on, github or gitlab [default: github]
+    --pages-url-template <TEMPLATE>
+                        Template of the Pages URL printed after deploying, using {host}, {user} and {repo}
+    --env <NAME>        Use the settings of the [env.<NAME>] table in .trunk-ghpages.toml
+    --no-sourcemaps     Leave *.map files out of the deploy
+    --only-extensions <EXT,...>
+                        Only deploy files with these extensions, besides html, wasm, js and css
+    --exclude-hidden    Leave hidden files and directories (starting with .) out of the deploy
+    --nojekyll          Write a .nojekyll file so Pages doesn't process the site with Jekyll
+    --keep-empty-dirs   Write a .gitkeep into every empty directory of dist, so it is deployed too
+    --branch-notice     Write a README.md saying the branch is generated and shouldn't be edited
+    --pages-branch-readme
+                        Write that README.md only on the first deploy to the branch, and keep the branch's one afterwards
+    --manifest          Write the SHA-256 of every deployed file to deploy-manifest.json
+    --line-ending <lf|crlf|keep>
+                        Line ending of the rewritten index.html [default: keep]
+    --temp-dir <DIR>    Directory to create temporary directories in [default: $TRUNK_GHPAGES_TMP, or the system temp directory]
+    --trash-git         Move the temporary .git directory to the trash instead of deleting it
+    --amend             Replace the single commit of the existing branch instead of creating a new one
+    --depth <N>         Number of commits to fetch from the existing branch, or 0 for all [default: 1]
+    --max-history <N>   Keep only the last N commits of the branch with --target-subdir, --version-dir or --commit-only, force-pushing the truncated history
+    --add-batch-size <N>
+                        Stage the files with one git add per N paths, for sites with huge numbers of files
+    --fast              Build the deploy commit with git plumbing instead of git add and git commit, for huge sites
+    --fix-permissions   Temporarily make a read-only index.html writable to rewrite it
+    --date-from-source  Date the deploy commit with the committer date of the source HEAD, in UTC unless --commit-tz is given
+    --commit-tz <OFFSET>
+                        Timezone offset like +00:00 to date the deploy commit in, instead of the local one
+    --message <TEMPLATE>
+                        Message of the deploy commit, where {branch} and {build} are replaced [default: Update {branch}]
+    --build-number <N>  Build number filled in for {build} in --message, such as the CI run number
+    --since-last-deploy Skip the deploy if the source HEAD is the commit the branch was last deployed from
+    --source-trailer    End the deploy commit message with Source-Commit and Source-Ref trailers for the source HEAD
+    --verify-links      Fail if index.html references local files that don't exist in dist
+    --rewrite <FROM=TO> Replace FROM with TO in the rewritten pages, after prefixing their paths. Can be given more than once
+    --inject-head <FILE>
+                        Insert the contents of FILE before </head> in index.html and the other rewritten pages
+    --asset-manifest <FILE>
+                        Only prefix the paths of the files listed in the JSON asset manifest FILE, instead of everything in dist
+    --rewrite-cmd <CMD> Rewrite index.html by piping it through the shell command CMD instead of prefixing its paths
+    --no-index-rewrite  Deploy dist as it is, without rewriting the paths in index.html
+    --check             Only check that the deploy could run, without changing anything or contacting the remote
+    --check-auth        Only check that the remote can be accessed with the configured credentials, without deploying
+    --repair            Only remove the .git directory left behind in dist by an interrupted deploy
+    --print-config      Only print the effective configuration after combining all settings, as JSON
+    --prune <PATTERN>   Delete the branches of the remote matching the glob PATTERN, such as old previews, instead of deploying
+    --list              Only list the files that would be deployed and their sizes, without rewriting or deploying
+    --format <text|json>
+                        Format of the output of --list and of the deploy result [default: text]
+    --show-diff         Print the changes made to index.html as a unified diff
+    --dry-run           Rewrite index.html in memory only, without writing it or deploying
+    --smoke-test        After pushing, check that index.html and its main JS and WASM files are served with status 200
+    --smoke-timeout <SECONDS>
+                        How long --smoke-test waits for the site to be served [default: 300]
+    --commit-only       Commit to the branch of the source repo in a worktree, leaving the push to you
+    --eject <DIR>       Stage the site in a worktree of the branch at DIR and print the commands to finish the deploy by hand
+    --offline           Do everything up to and including the deploy commit, but don't fetch from or push to the remote
+    --force             Skip safety checks, such as the remote host matching the provider or the source repo being on another branch
+    --strict            Fail if any warnings are given, before pushing when possible
+    -v, --verbose       Print every git command run, with its exit code and duration
+    -q, --quiet         Don't print warnings
+    -h, --help          Print this help message";
+
+/// A utility command run instead of a deploy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subcommand {
+    /// Create a .nojekyll file in the given directory, or dist
+    TouchNojekyll { dir: Option<String> },
+}
+
+/// Format of the output of --list and of the deploy result
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown format '{}', expected 'text' or 'json'", s))),
+        }
+    }
+}
+
+/// Options passed to trunk-ghpages on the command line
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Utility command to run instead of deploying
+    pub command: Option<Subcommand>,
+    /// Rewrite every *.html file in dist instead of only the top-level index.html
+    pub recursive_html: bool,
+    /// Skip the confirmation prompt before force-pushing over an existing branch
+    pub yes: bool,
+    /// Run `trunk build --release` before deploying
+    pub build: bool,
+    /// Override for the trunk executable
+    pub trunk_bin: Option<String>,
+    /// Override for the git executable
+    pub git_bin: Option<String>,
+    /// URL of the repo to deploy to, instead of the remote origin
+    pub repo: Option<String>,
+    /// Repos and branches to deploy to, one after the other
+    pub targets: Vec<(String, String)>,
+    /// Stop deploying to the targets after the first one fails
+    pub fail_fast: bool,
+    /// Environment variable to read the URL of the repo to deploy to from
+    pub remote_url_env: Option<String>,
+    /// Access token to push to an HTTP(S) remote with
+    pub token: Option<String>,
+    /// Provide the token with a credential helper instead of the remote URL
+    pub credential_helper: bool,
+    /// Branch to deploy to
+    pub branch: Option<String>,
+    /// Directory containing the built site
+    pub dist: Option<String>,
+    /// Archive of the built site to deploy instead of dist
+    pub archive: Option<String>,
+    /// Fail if the branch doesn't exist on the remote yet
+    pub require_existing_branch: bool,
+    /// Subdirectory of the branch to deploy into
+    pub target_subdir: Option<String>,
+    /// Version folder of the branch to deploy into
+    pub version_dir: Option<String>,
+    /// Folder of a user pages repo to deploy into and serve the site under
+    pub mount: Option<String>,
+    /// Redirect the root of the branch to the subdirectory deployed into
+    pub root_redirect: bool,
+    /// Custom domain to write to a CNAME file
+    pub cname: Option<String>,
+    /// Pages provider the site is hosted on
+    pub provider: Option<Provider>,
+    /// Template of the Pages URL printed after deploying
+    pub pages_url_template: Option<String>,
+    /// Name of the config file environment to use
+    pub env: Option<String>,
+    /// Leave *.map files out of the deploy
+    pub no_sourcemaps: bool,
+    /// Leave hidden files and directories out of the deploy
+    pub exclude_hidden: bool,
+    /// Lowercase extensions, without the dot, of the only files to deploy besides the web assets
+    pub only_extensions: Vec<String>,
+    /// Write a .nojekyll file into the deployed site
+    pub nojekyll: bool,
+    /// Write a .gitkeep into empty directories
+    pub keep_empty_dirs: bool,
+    /// Stage the files with one `git add` per this many paths, instead of a single `git add -A`
+    pub add_batch_size: Option<NonZeroUsize>,
+    /// Build the deploy commit with git plumbing instead of `git add` and `git commit`
+    pub fast: bool,
+    /// Write a README.md warning against editing the branch
+    pub branch_notice: bool,
+    /// Write the README.md of --branch-notice only when the branch is created, and keep the existing one afterwards
+    pub pages_branch_readme: bool,
+    /// Write a manifest of deployed file hashes
+    pub manifest: bool,
+    /// Line ending of the rewritten index.html
+    pub line_ending: LineEnding,
+    /// Directory to create temporary directories in
+    pub temp_dir: Option<String>,
+    /// Move the temporary .git directory to the trash instead of deleting it
+    pub trash_git: bool,
+    /// Amend the single commit of the existing branch
+    pub amend: bool,
+    /// Number of commits to fetch from the existing branch
+    pub depth: Option<u32>,
+    /// Number of commits to keep on the branch when deploying into a subdirectory or with --commit-only
+    pub max_history: Option<NonZeroU32>,
+    /// Temporarily make a read-only index.html writable
+    pub fix_permissions: bool,
+    /// Use the committer date of the source HEAD for the deploy commit
+    pub date_from_source: bool,
+    /// Timezone offset of the deploy commit's dates, in git's `+hhmm` format
+    pub commit_tz: Option<String>,
+    /// Add trailers linking the deploy commit to the source commit
+    pub source_trailer: bool,
+    /// Skip the deploy if the source didn't change since the last one
+    pub since_last_deploy: bool,
+    /// Template of the deploy commit message
+    pub message: Option<String>,
+    /// Build number for the {build} placeholder of --message
+    pub build_number: Option<u64>,
+    /// Check that every local reference in index.html exists in dist
+    pub verify_links: bool,
+    /// Literal replacements applied to the rewritten pages, in order
+    pub rewrites: Vec<(String, String)>,
+    /// File whose contents are inserted into the head of the rewritten pages
+    pub inject_head: Option<String>,
+    /// JSON manifest listing the files of dist whose root-relative paths are prefixed
+    pub asset_manifest: Option<String>,
+    /// Shell command to rewrite index.html with, instead of prefixing its paths
+    pub rewrite_cmd: Option<String>,
+    /// Skip rewriting index.html
+    pub no_index_rewrite: bool,
+    /// Only run the local checks of the deploy
+    pub check: bool,
+    /// Only check access to the remote
+    pub check_auth: bool,
+    /// Only remove a leftover dist/.git
+    pub repair: bool,
+    /// Only print the effective configuration
+    pub print_config: bool,
+    /// Glob of the remote branches to delete instead of deploying
+    pub prune: Option<String>,
+    /// Only list the files that would be deployed
+    pub list: bool,
+    /// Format of the output of --list and of the deploy result
+    pub format: OutputFormat,
+    /// Print the changes made to index.html as a unified diff
+    pub show_diff: bool,
+    /// Leave dist unchanged and don't deploy
+    pub dry_run: bool,
+    /// Check that the pushed site is served correctly
+    pub smoke_test: bool,
+    /// Seconds --smoke-test waits for the site
+    pub smoke_timeout: Option<u64>,
+    /// Create the deploy commit on the local branch of the source repo instead of pushing it
+    pub commit_only: bool,
+    /// Directory to set up a worktree of the branch in, with the site staged but not committed
+    pub eject: Option<String>,
+    /// Stop before anything that talks to the remote
+    pub offline: bool,
+    /// Skip safety checks that would otherwise abort the deploy
+    pub force: bool,
+    /// Fail if any warnings are given
+    pub strict: bool,
+    /// Print debug messages, such as the git commands run
+    pub verbose: bool,
+    /// Don't print warnings
+    pub quiet: bool,
+    /// Print the usage message and exit
+    pub help: bool,
+}
+
+impl Options {
+    /// Parses the given command line arguments (not including the executable name) into Options
+    ///
+    /// # Example:
+    /// ```no_run
+    /// # use trunk_ghpages::cli::Options;
+    /// let opts = Options::parse(std::env::args().skip(1))?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, Error> {
+        let mut opts = Options::default();
+        let mut args = args.into_iter();
+        let mut positional = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--recursive-html" => opts.recursive_html = true,
+                "-y" | "--yes" => opts.yes = true,
+                "--build" => opts.build = true,
+                "--trunk-bin" => opts.trunk_bin = Some(value(&mut args, &arg)?),
+                "--git-bin" => opts.git_bin = Some(value(&mut args, &arg)?),
+                "--repo" => opts.repo = Some(value(&mut args, &arg)?),
+                "--target" => opts.targets.push(target(&value(&mut args, &arg)?)?),
+                "--fail-fast" => opts.fail_fast = true,
+                "--remote-url-env" => opts.remote_url_env = Some(value(&mut args, &arg)?),
+                "--token" => opts.token = Some(value(&mut args, &arg)?),
+                "--credential-helper" => opts.credential_helper = true,
+                "--branch" => opts.branch = Some(value(&mut args, &arg)?),
+                "--dist" => opts.dist = Some(value(&mut args, &arg)?),
+                "--archive" => opts.archive = Some(value(&mut args, &arg)?),
+                "--require-existing-branch" => opts.require_existing_branch = true,
+                "--target-subdir" => opts.target_subdir = Some(value(&mut args, &arg)?),
+                "--version-dir" => opts.version_dir = Some(value(&mut args, &arg)?),
+                "--mount" => opts.mount = Some(value(&mut args, &arg)?),
+                "--root-redirect" => opts.root_redirect = true,
+                "--cname" => opts.cname = Some(value(&mut args, &arg)?),
+                "--provider" => opts.provider = Some(value(&mut args, &arg)?.parse()?),
+                "--pages-url-template" => opts.pages_url_template = Some(value(&mut args, &arg)?),
+                "--env" => opts.env = Some(value(&mut args, &arg)?),
+                "--no-sourcemaps" => opts.no_sourcemaps = true,
+                "--exclude-hidden" => opts.exclude_hidden = true,
+                "--only-extensions" => opts.only_extensions = value(&mut args, &arg)?
+                    .split(',')
+                    .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                    .filter(|ext| !ext.is_empty())
+                    .collect(),
+                "--nojekyll" => opts.nojekyll = true,
+                "--keep-empty-dirs" => opts.keep_empty_dirs = true,
+                "--add-batch-size" => opts.add_batch_size = Some(number(&mut args, &arg)?),
+                "--fast" => opts.fast = true,
+                "--branch-notice" => opts.branch_notice = true,
+                "--pages-branch-readme" => opts.pages_branch_readme = true,
+                "--manifest" => opts.manifest = true,
+                "--line-ending" => opts.line_ending = value(&mut args, &arg)?.parse()?,
+                "--temp-dir" => opts.temp_dir = Some(value(&mut args, &arg)?),
+                "--trash-git" => opts.trash_git = true,
+                "--amend" => opts.amend = true,
+                "--depth" => opts.depth = Some(number(&mut args, &arg)?),
+                "--max-history" => opts.max_history = Some(number(&mut args, &arg)?),
+                "--fix-permissions" => opts.fix_permissions = true,
+                "--date-from-source" => opts.date_from_source = true,
+                "--commit-tz" => opts.commit_tz = Some(timezone_offset(&value(&mut args, &arg)?)?),
+                "--source-trailer" => opts.source_trailer = true,
+                "--since-last-deploy" => opts.since_last_deploy = true,
+                "--message" => opts.message = Some(value(&mut args, &arg)?),
+                "--build-number" => opts.build_number = Some(number(&mut args, &arg)?),
+                "--verify-links" => opts.verify_links = true,
+                "--rewrite" => opts.rewrites.push(rewrite_rule(&value(&mut args, &arg)?)?),
+                "--inject-head" => opts.inject_head = Some(value(&mut args, &arg)?),
+                "--asset-manifest" => opts.asset_manifest = Some(value(&mut args, &arg)?),
+                "--rewrite-cmd" => opts.rewrite_cmd = Some(value(&mut args, &arg)?),
+                "--no-index-rewrite" => opts.no_index_rewrite = true,
+                "--check" => opts.check = true,
+                "--check-auth" => opts.check_auth = true,
+                "--repair" => opts.repair = true,
+                "--print-config" => opts.print_config = true,
+                "--prune" => opts.prune = Some(value(&mut args, &arg)?),
+                "--list" => opts.list = true,
+                "--format" => opts.format = value(&mut args, &arg)?.parse()?,
+                "--show-diff" => opts.show_diff = true,
+                "--dry-run" => opts.dry_run = true,
+                "--smoke-test" => opts.smoke_test = true,
+                "--smoke-timeout" => opts.smoke_timeout = Some(number(&mut args, &arg)?),
+                "--commit-only" => opts.commit_only = true,
+                "--eject" => opts.eject = Some(value(&mut args, &arg)?),
+                "--offline" => opts.offline = true,
+                "--force" => opts.force = true,
+                "--strict" => opts.strict = true,
+                "-v" | "--verbose" => opts.verbose = true,
+                "-q" | "--quiet" => opts.quiet = true,
+                "-h" | "--help" => opts.help = true,
+                _ if !arg.starts_with('-') => positional.push(arg),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, format!("Unknown argument '{}'. See --help for usage", arg))),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        opts.command = match positional.next().as_deref() {
+            None => None,
+            Some("touch-nojekyll") => Some(Subcommand::TouchNojekyll { dir: positional.next() }),
+            Some(command) => return Err(Error::new(ErrorKind::InvalidInput, format!("Unknown command '{}'. See --help for usage", command))),
+        };
+        if let Some(arg) = positional.next() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Unexpected argument '{}'. See --help for usage", arg)))
+        }
+
+        Ok(opts)
+    }
+
+    /// Fills in any options that weren't given on the command line from `settings`
+    pub fn apply(&mut self, settings: &Settings) -> Result<(), Error> {
+        self.repo = self.repo.take().or(settings.remote.clone());
+        self.branch = self.branch.take().or(settings.branch.clone());
+        self.dist = self.dist.take().or(settings.dist.clone());
+        self.cname = self.cname.take().or(settings.cname.clone());
+        if let (None, Some(provider)) = (self.provider, &settings.provider) {
+            self.provider = Some(provider.parse()?);
+        }
+        Ok(())
+    }
+
+    /// The branch to deploy to
+    pub fn branch(&self) -> &str {
+        self.branch.as_deref().unwrap_or("gh-pages")
+    }
+
+    /// The directory containing the built site, relative to the project directory
+    pub fn dist(&self) -> &str {
+        self.dist.as_deref().unwrap_or("dist")
+    }
+
+    /// The subdirectory of the branch to deploy into, if any, from --target-subdir, --version-dir or --mount
+    pub fn subdir(&self) -> Result<Option<&str>, Error> {
+        let subdirs: Vec<(&str, &str)> = [("--target-subdir", &self.target_subdir), ("--version-dir", &self.version_dir), ("--mount", &self.mount)]
+            .into_iter()
+            .filter_map(|(flag, subdir)| Some((flag, subdir.as_deref()?)))
+            .collect();
+        match subdirs.as_slice() {
+            [] => Ok(None),
+            [(_, subdir)] => Ok(Some(subdir)),
+            [(first, _), (second, _), ..] => Err(Error::new(ErrorKind::InvalidInput, format!("{} and {} can't be used together", first, second))),
+        }
+    }
+
+    /// The Pages provider the site is hosted on
+    pub fn provider(&self) -> Provider {
+        self.provider.unwrap_or_default()
+    }
+
+    /// The number of commits to fetch from the existing branch, where 0 means the full history. 
+    /// With --max-history, at least the commits that are kept are fetched
+    pub fn depth(&self) -> u32 {
+        let depth = self.depth.unwrap_or(1);
+        match self.max_history {
+            Some(max) if depth != 0 => depth.max(max.get()),
+            _ => depth,
+        }
+    }
+
+    /// Seconds --smoke-test waits for the site to be served
+    pub fn smoke_timeout(&self) -> u64 {
+        self.smoke_timeout.unwrap_or(300)
+    }
+
+    /// The trunk executable to run
+    pub fn trunk_bin(&self) -> &str {
+        self.trunk_bin.as_deref().unwrap_or("trunk")
+    }
+
+    /// The git executable to run
+    pub fn git_bin(&self) -> &str {
+        self.git_bin.as_deref().unwrap_or("git")
+    }
+}
+
+/// Takes the value following the flag `flag`, or returns an error if there is none
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, Error> {
+    args.next().ok_or(Error::new(ErrorKind::InvalidInput, format!("Missing value for '{}'. See --help for usage", flag)))
+}
+
+/// Splits a `--target` into the repo URL and the branch, which follows the last `:`. 
+/// 
+/// Since scp-like URLs and ports contain a `:` too, a target without a branch would otherwise be split inside of its URL, 
+/// like `git@github.com:a/site.git` into `git@github.com` and `a/site.git`, so the URL has to have an owner/repo path.
+fn target(target: &str) -> Result<(String, String), Error> {
+    let has_repo_path = |url: &str| {
+        let remote = RemoteUrl::parse(url);
+        remote.path.contains('/') && !remote.owner().is_empty() && !remote.repo_name().is_empty()
+    };
+    match target.rsplit_once(':') {
+        Some((url, branch)) if has_repo_path(url) && !branch.is_empty() && !branch.starts_with("//") => Ok((url.to_string(), branch.to_string())),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!(
+            "Expected a target like URL:BRANCH for '--target', found '{}'. The branch can't be left out", target))),
+    }
+}
+
+/// Splits a `--rewrite` rule into the text to replace and its replacement
+fn rewrite_rule(rule: &str) -> Result<(String, String), Error> {
+    match rule.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Expected a rule like FROM=TO for '--rewrite', found '{}'", rule))),
+    }
+}
+
+/// Validates a `--commit-tz` offset like `+02:00` or `-0530`, and converts it to the `+hhmm` format git uses in dates
+fn timezone_offset(offset: &str) -> Result<String, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("Expected an offset like +00:00 for '--commit-tz', found '{}'", offset));
+    let (sign, rest) = offset.split_at_checked(1).filter(|(sign, _)| matches!(*sign, "+" | "-")).ok_or_else(invalid)?;
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest.get(..2).unwrap_or(rest), rest.get(2..).unwrap_or("")));
+    let digits = |part: &str, max: u32| (part.len() == 2 && part.chars().all(|c| c.is_ascii_digit()))
+        .then(|| part.parse::<u32>().ok()).flatten().filter(|value| *value <= max);
+    match (digits(hours, 14), digits(minutes, 59)) {
+        (Some(_), Some(_)) => Ok(format!("{}{}{}", sign, hours, minutes)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Takes the value following the flag `flag` and parses it as a number
+fn number<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, Error> {
+    let value = value(args, flag)?;
+    value.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("Expected a number for '{}', found '{}'", flag, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timezone_offset_is_converted_for_git() {
+        for (offset, expected) in [("+02:00", "+0200"), ("-05:30", "-0530"), ("+0000", "+0000"), ("-1200", "-1200"), ("+14:00", "+1400")] {
+            assert_eq!(timezone_offset(offset).unwrap(), expected);
+        }
+        for offset in ["02:00", "+2:00", "+15:00", "+02:60", "+02", "UTC", "", "+02:00:00"] {
+            assert!(timezone_offset(offset).is_err(), "{} should be rejected", offset);
+        }
+    }
+
+    #[test]
+    fn target_splits_at_the_last_colon() {
+        for (input, url, branch) in [
+            ("git@github.com:a/site.git:gh-pages", "git@github.com:a/site.git", "gh-pages"),
+            ("https://github.com/a/site.git:main", "https://github.com/a/site.git", "main"),
+            ("ssh://git@github.com:22/a/site.git:pages/v2", "ssh://git@github.com:22/a/site.git", "pages/v2"),
+            ("../mirror/site.git:gh-pages", "../mirror/site.git", "gh-pages"),
+        ] {
+            assert_eq!(target(input).unwrap(), (url.to_string(), branch.to_string()), "{}", input);
+        }
+    }
+
+    #[test]
+    fn target_without_a_branch_is_rejected() {
+        for input in ["git@github.com:a/site.git", "ssh://git@github.com:22/a/site.git", "https://github.com/a/site.git", "git@github.com:a/site.git:", "gh-pages"] {
+            assert!(target(input).is_err(), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::{collections::BTreeMap, env, fs::read_to_string, io::{Error, ErrorKind}, path::Path};
+
+/// Name of the config file looked for in the project directory
+pub const CONFIG_FILE: &str = ".trunk-ghpages.toml";
+
+/// Name of Trunk's config file, which can hold the settings in a `[tool.trunk-ghpages]` table too
+pub const TRUNK_CONFIG_FILE: &str = "Trunk.toml";
+
+const TRUNK_CONFIG_TABLE: &str = "tool.trunk-ghpages";
+
+/// Settings that can be given in the config file, either at the top level or in an `[env.<name>]` table
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    pub remote: Option<String>,
+    pub branch: Option<String>,
+    pub cname: Option<String>,
+    pub dist: Option<String>,
+    pub provider: Option<String>,
+}
+
+impl Settings {
+    /// Reads the settings given by the `TRUNK_GHPAGES_REMOTE`, `TRUNK_GHPAGES_BRANCH` and `TRUNK_GHPAGES_DIST`
+    /// environment variables. Variables that aren't set or are empty are ignored, 
+    /// but a variable that isn't valid UTF-8 is an error rather than being silently ignored as well.
+    pub fn from_env() -> Result<Settings, Error> {
+        let var = |name: &str| match env::var(name) {
+            Ok(value) => Ok(Some(value).filter(|value| !value.is_empty())),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(env::VarError::NotUnicode(value)) => Err(Error::new(ErrorKind::InvalidData,
+                format!("The environment variable {} isn't valid UTF-8: {:?}", name, value))),
+        };
+        Ok(Settings {
+            remote: var("TRUNK_GHPAGES_REMOTE")?,
+            branch: var("TRUNK_GHPAGES_BRANCH")?,
+            dist: var("TRUNK_GHPAGES_DIST")?,
+            ..Settings::default()
+        })
+    }
+}
+
+/// The contents of a `.trunk-ghpages.toml` config file
+///
+/// Only a small subset of TOML is supported: `[table]` headers, `key = value` pairs
+/// with string, boolean or integer values, and `#` comments.
+/// ```toml
+/// branch = "gh-pages"
+///
+/// [env.prod]
+/// remote = "https://github.com/FradulentUser/MyRepo.git"
+/// cname = "example.com"
+/// ```
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    /// Settings given outside of any table
+    pub settings: Settings,
+    /// Settings of each `[env.<name>]` table, by name
+    pub envs: BTreeMap<String, Settings>,
+}
+
+impl ConfigFile {
+    /// Reads the config file in `dir`, or returns an empty config if there is none
+    ///
+    /// # Example:
+    /// ```no_run
+    /// # use trunk_ghpages::config::ConfigFile;
+    /// let cwd = std::env::current_dir()?;
+    /// let config = ConfigFile::load(&cwd)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn load(dir: &Path) -> Result<ConfigFile, Error> {
+        let path = dir.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(ConfigFile::default())
+        }
+        ConfigFile::parse(&read_to_string(&path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads the settings of the `[tool.trunk-ghpages]` table in the `Trunk.toml` of `dir`, 
+    /// so they can live next to the rest of the build config. Everything else in `Trunk.toml` belongs to Trunk and is ignored.
+    pub fn load_trunk_toml(dir: &Path) -> Result<Settings, Error> {
+        let path = dir.join(TRUNK_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Settings::default())
+        }
+        let text = read_to_string(&path)?;
+        // only the lines of the table are parsed, since the rest can use TOML syntax the parser doesn't support
+        let mut in_table = false;
+        let mut table = String::new();
+        for line in text.lines() {
+            if line.trim_start().starts_with('[') {
+                in_table = strip_comment(line).trim().strip_prefix('[').and_then(|line| line.strip_suffix(']'))
+                    .is_some_and(|name| name.trim() == TRUNK_CONFIG_TABLE);
+            } else if in_table {
+                table.push_str(line);
+                table.push('\n');
+            }
+        }
+        ConfigFile::parse(&table)
+            .map(|config| config.settings)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("[{}] of {}: {}", TRUNK_CONFIG_TABLE, path.display(), e)))
+    }
+
+    /// Parses the contents of a config file
+    pub fn parse(text: &str) -> Result<ConfigFile, String> {
+        let mut config = ConfigFile::default();
+
+        for (table, entries) in parse_tables(text)? {
+            let settings = if table.is_empty() {
+                &mut config.settings
+            } else if let Some(name) = table.strip_prefix("env.") {
+                config.envs.entry(name.to_string()).or_default()
+            } else {
+                return Err(format!("unknown table [{}]", table))
+            };
+
+            for (key, value) in entries {
+                let field = match key.as_str() {
+                    "remote" => &mut settings.remote,
+                    "branch" => &mut settings.branch,
+                    "cname" => &mut settings.cname,
+                    "dist" => &mut settings.dist,
+                    "provider" => &mut settings.provider,
+                    _ => return Err(format!("unknown key '{}' in {}", key, table_name(&table))),
+                };
+                *field = Some(value);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Gets the settings of the `[env.<name>]` table, or returns an error if it isn't defined
+    pub fn env(&self, name: &str) -> Result<&Settings, Error> {
+        self.envs.get(name).ok_or(Error::new(ErrorKind::NotFound,
+            format!("Environment '{}' is not defined. Add an [env.{}] table to {}", name, name, CONFIG_FILE)))
+    }
+}
+
+fn table_name(table: &str) -> String {
+    if table.is_empty() { "the top level".to_string() } else { format!("[{}]", table) }
+}
+
+/// Splits TOML text into its tables, mapping each table name (empty for the top level)
+/// to its `key = value` pairs. String values are unquoted.
+fn parse_tables(text: &str) -> Result<BTreeMap<String, BTreeMap<String, String>>, String> {
+    let mut tables: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut table = String::new();
+
+    for (idx, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            table = name.trim().to_string();
+            tables.entry(table.clone()).or_default();
+            continue
+        }
+
+        let (key, value) = line.split_once('=').ok_or(format!("line {}: expected `key = value`", idx + 1))?;
+        let value = value.trim();
+        let value = match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+            Some(string) => string.to_string(),
+            None if value == "true" || value == "false" || value.parse::<i64>().is_ok() => value.to_string(),
+            None => return Err(format!("line {}: unsupported value `{}`", idx + 1, value)),
+        };
+        tables.entry(table.clone()).or_default().insert(key.trim().to_string(), value);
+    }
+
+    Ok(tables)
+}
+
+/// Removes a trailing `#` comment from a line, ignoring any `#` inside of a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_env_reads_the_variables() {
+        env::set_var("TRUNK_GHPAGES_REMOTE", "https://github.com/FradulentUser/MyRepo.git");
+        env::set_var("TRUNK_GHPAGES_BRANCH", "pages");
+        env::set_var("TRUNK_GHPAGES_DIST", "");
+        let settings = Settings::from_env();
+        #[cfg(unix)]
+        let invalid = {
+            use std::os::unix::ffi::OsStrExt;
+            env::set_var("TRUNK_GHPAGES_DIST", std::ffi::OsStr::from_bytes(b"dist-\xff"));
+            Settings::from_env()
+        };
+        for var in ["TRUNK_GHPAGES_REMOTE", "TRUNK_GHPAGES_BRANCH", "TRUNK_GHPAGES_DIST"] {
+            env::remove_var(var);
+        }
+
+        let settings = settings.unwrap();
+        assert_eq!(settings.remote.as_deref(), Some("https://github.com/FradulentUser/MyRepo.git"));
+        assert_eq!(settings.branch.as_deref(), Some("pages"));
+        // empty variables are ignored
+        assert_eq!(settings.dist, None);
+        #[cfg(unix)]
+        {
+            let e = invalid.unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+            assert!(e.to_string().contains("TRUNK_GHPAGES_DIST isn't valid UTF-8"), "{}", e);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+/// Number of unchanged lines shown around each change
+const CONTEXT: usize = 3;
+
+/// Largest number of cells in the table of the longest common subsequence (64 MB of them), 
+/// above which the changed lines are only summarized instead of diffed
+const MAX_LCS_CELLS: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Computes a unified diff between `old` and `new`, labelling both sides with `name`.
+/// Returns an empty string if there are no changes.
+/// 
+/// Lines are compared with their line endings, so if only those changed (like from CRLF to LF), that is all the diff says, 
+/// instead of listing every line as changed. Changes too big to diff, such as between two huge files without 
+/// any lines in common, are summarized with the number of changed lines instead.
+///
+/// # Example:
+/// ```ignore
+/// let diff = unified_diff("index.html", "<a href=\"/b\">\n", "<a href=\"/repo/b\">\n");
+/// ```
+pub fn unified_diff(name: &str, old: &str, new: &str) -> String {
+    if old == new {
+        return String::new()
+    }
+    let mut out = format!("--- a/{}\n+++ b/{}\n", name, name);
+    if old.replace("\r\n", "\n") == new.replace("\r\n", "\n") {
+        let (from, to) = if old.contains("\r\n") { ("CRLF", "LF") } else { ("LF", "CRLF") };
+        out.push_str(&format!("Only the line endings changed, from {} to {}\n", from, to));
+        return out
+    }
+
+    let old: Vec<&str> = old.split_inclusive('\n').collect();
+    let new: Vec<&str> = new.split_inclusive('\n').collect();
+    let Some(ops) = diff_lines(&old, &new) else {
+        out.push_str(&format!("{} lines changed to {} lines, which is too big a change to diff\n", old.len(), new.len()));
+        return out
+    };
+
+    // line numbers in old and new at the start of each op
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for (op, _) in &ops {
+        positions.push((old_line, new_line));
+        match op {
+            Op::Equal => { old_line += 1; new_line += 1; }
+            Op::Delete => old_line += 1,
+            Op::Insert => new_line += 1,
+        }
+    }
+
+    let mut idx = 0;
+    while let Some(first_change) = ops[idx..].iter().position(|(op, _)| *op != Op::Equal).map(|pos| pos + idx) {
+        // extend the hunk until there are more than 2 * CONTEXT unchanged lines in a row
+        let start = first_change.saturating_sub(CONTEXT);
+        let mut end = first_change;
+        let mut equal_run = 0;
+        for (pos, (op, _)) in ops.iter().enumerate().skip(first_change) {
+            if *op == Op::Equal {
+                equal_run += 1;
+                if equal_run > 2 * CONTEXT {
+                    break
+                }
+            } else {
+                equal_run = 0;
+                end = pos;
+            }
+        }
+        let end = (end + CONTEXT + 1).min(ops.len());
+
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|(op, _)| *op != Op::Insert).count();
+        let new_count = hunk.iter().filter(|(op, _)| *op != Op::Delete).count();
+        let (old_start, new_start) = positions[start];
+        out.push_str(&format!("@@ -{} +{} @@\n", range(old_start, old_count), range(new_start, new_count)));
+        for (op, line) in hunk {
+            out.push(match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            });
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+
+        idx = end;
+    }
+
+    out
+}
+
+/// Formats the line range of a hunk, which is 1-indexed unless it is empty
+fn range(start: usize, count: usize) -> String {
+    if count == 0 { format!("{},0", start) } else { format!("{},{}", start + 1, count) }
+}
+
+/// Finds the operations turning `old` into `new` using the longest common subsequence of their lines, 
+/// or None if the changed lines are too many to find it with at most [MAX_LCS_CELLS]
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<(Op, &'a str)>> {
+    // lines shared at the start and end don't need to go through the quadratic part
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    if old_mid.len().saturating_mul(new_mid.len()) > MAX_LCS_CELLS {
+        return None
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old_mid[i..] and new_mid[j..]
+    let width = new_mid.len() + 1;
+    let mut lcs = vec![0u32; (old_mid.len() + 1) * width];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<(Op, &str)> = old[..prefix].iter().map(|line| (Op::Equal, *line)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            ops.push((Op::Equal, old_mid[i]));
+            i += 1;
+            j += 1;
+        } else if j < new_mid.len() && (i == old_mid.len() || lcs[i * width + j + 1] >= lcs[(i + 1) * width + j]) {
+            ops.push((Op::Insert, new_mid[j]));
+            j += 1;
+        } else {
+            ops.push((Op::Delete, old_mid[i]));
+            i += 1;
+        }
+    }
+    // deletions read better before the insertions replacing them
+    normalize_changes(&mut ops[prefix..]);
+    ops.extend(old[old.len() - suffix..].iter().map(|line| (Op::Equal, *line)));
+    Some(ops)
+}
+
+/// Reorders each run of changed lines so that its deletions come before its insertions
+fn normalize_changes(ops: &mut [(Op, &str)]) {
+    let mut start = 0;
+    while start < ops.len() {
+        if ops[start].0 == Op::Equal {
+            start += 1;
+            continue
+        }
+        let len = ops[start..].iter().take_while(|(op, _)| *op != Op::Equal).count();
+        // stable sort keeps the order of lines within the deletions and within the insertions
+        ops[start..start + len].sort_by_key(|(op, _)| *op == Op::Insert);
+        start += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_changed_lines_with_context() {
+        let old = "<html>\n<head>\n<script src=\"/app.js\"></script>\n</head>\n</html>\n";
+        let new = "<html>\n<head>\n<script src=\"/MyRepo/app.js\"></script>\n</head>\n</html>\n";
+        assert_eq!(unified_diff("index.html", old, new), "--- a/index.html\n+++ b/index.html\n@@ -1,5 +1,5 @@\n \
+            <html>\n <head>\n-<script src=\"/app.js\"></script>\n+<script src=\"/MyRepo/app.js\"></script>\n </head>\n </html>\n");
+        assert_eq!(unified_diff("index.html", old, old), "");
+    }
+
+    #[test]
+    fn reports_line_ending_only_changes() {
+        assert_eq!(unified_diff("index.html", "a\r\nb\r\n", "a\nb\n"), "--- a/index.html\n+++ b/index.html\nOnly the line endings changed, from CRLF to LF\n");
+        assert_eq!(unified_diff("index.html", "a\nb\n", "a\r\nb\r\n"), "--- a/index.html\n+++ b/index.html\nOnly the line endings changed, from LF to CRLF\n");
+        // a line whose ending changed along with its content shows up as changed
+        assert!(unified_diff("index.html", "a\r\nb\r\n", "a\r\nc\n").contains("-b\r\n+c\n"));
+    }
+
+    #[test]
+    fn marks_a_missing_newline_at_the_end() {
+        assert_eq!(unified_diff("index.html", "a", "b"), "--- a/index.html\n+++ b/index.html\n@@ -1,1 +1,1 @@\n\
+            -a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n");
+    }
+
+    #[test]
+    fn summarizes_changes_too_big_to_diff() {
+        let old: String = (0..5000).map(|n| format!("old {}\n", n)).collect();
+        let new: String = (0..5000).map(|n| format!("new {}\n", n)).collect();
+        assert_eq!(unified_diff("index.html", &old, &new), "--- a/index.html\n+++ b/index.html\n\
+            5000 lines changed to 5000 lines, which is too big a change to diff\n");
+    }
+}
@@ -0,0 +1,185 @@
+use std::{io::{Error, ErrorKind}, path::Path};
+
+/// Gets the values of every `name` attribute in `html`, e.g. every `src` for `attribute_values(html, "src")`. 
+/// Both quoted and unquoted values are supported.
+pub fn attribute_values<'a>(html: &'a str, name: &str) -> Vec<&'a str> {
+    let lower = html.to_ascii_lowercase();
+    let mut values = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(idx) = lower[search_from..].find(name).map(|idx| idx + search_from) {
+        search_from = idx + name.len();
+
+        // the name must be a whole attribute name inside of a tag, followed by `=`
+        if !lower[..idx].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue
+        }
+        let rest = lower[search_from..].trim_start();
+        let Some(rest) = rest.strip_prefix('=') else {
+            continue
+        };
+        let value_start = html.len() - rest.trim_start().len();
+        let value = &html[value_start..];
+
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or(""),
+            _ => value.split(|c: char| c.is_ascii_whitespace() || c == '>').next().unwrap_or(""),
+        };
+        values.push(value);
+    }
+
+    values
+}
+
+/// Gets the `href` of every icon link in `html`, such as `<link rel="icon">`, `<link rel="shortcut icon">` 
+/// or `<link rel="apple-touch-icon">`
+pub fn icon_links(html: &str) -> Vec<&str> {
+    let lower = html.to_ascii_lowercase();
+    let mut hrefs = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(start) = lower[search_from..].find("<link").map(|idx| idx + search_from) {
+        let end = lower[start..].find('>').map_or(html.len(), |idx| start + idx + 1);
+        search_from = end;
+        let tag = &html[start..end];
+        let is_icon = attribute_values(tag, "rel").iter().any(|rel| rel.to_ascii_lowercase().split_whitespace().any(|rel| rel.ends_with("icon")));
+        if is_icon {
+            hrefs.extend(attribute_values(tag, "href"));
+        }
+    }
+
+    hrefs
+}
+
+/// Gets the references to the assets of the page in `html`: the `src` of scripts, images and media and their sources, 
+/// and the `href` of links such as stylesheets, preloads and icons. Links to other pages like `<a href>` aren't assets, 
+/// and aren't included.
+pub fn asset_references(html: &str) -> Vec<&str> {
+    let lower = html.to_ascii_lowercase();
+    let mut references = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(start) = lower[search_from..].find('<').map(|idx| idx + search_from) {
+        let end = lower[start..].find('>').map_or(html.len(), |idx| start + idx + 1);
+        search_from = end;
+        let name_end = lower[start + 1..end].find(|c: char| !c.is_ascii_alphanumeric()).map_or(end, |idx| start + 1 + idx);
+        let attribute = match &lower[start + 1..name_end] {
+            "link" => "href",
+            "script" | "img" | "source" | "video" | "audio" | "track" | "embed" => "src",
+            _ => continue,
+        };
+        references.extend(attribute_values(&html[start..end], attribute));
+    }
+
+    references
+}
+
+/// Whether `url` points to a file of the deployed site, rather than to another site or not to a file at all
+pub fn is_local_reference(url: &str) -> bool {
+    let url = url.trim();
+    !(url.is_empty() || url.starts_with('#') || url.starts_with("//") || url.contains("://")
+        || ["data:", "mailto:", "tel:", "javascript:", "blob:"].iter().any(|scheme| url.to_ascii_lowercase().starts_with(scheme)))
+}
+
+/// Checks that every local asset reference of [asset_references] in `html` (the deployed index.html) refers to a file in `dist`, 
+/// once the site is served under `/{site_path}/`. The root of the site and paths ending in `/` are directories, 
+/// which are served as long as they are inside the site. Returns an error listing every missing reference.
+///
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::html::verify_links;
+/// # let index_html = String::new();
+/// let cwd = std::env::current_dir()?;
+/// verify_links(&index_html, &cwd.join("dist"), "MyRepo")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn verify_links(html: &str, dist: &Path, site_path: &str) -> Result<(), Error> {
+    let site_prefix = format!("/{}/", site_path.trim_matches('/')).replace("//", "/");
+
+    let mut missing = Vec::new();
+    for url in asset_references(html) {
+        if !is_local_reference(url) {
+            continue
+        }
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+
+        // root-relative references only work if they are inside the site's path
+        let relative = match path.strip_prefix('/') {
+            Some(_) => path.strip_prefix(&site_prefix),
+            None => Some(path),
+        };
+        let is_site_root = path.trim_end_matches('/') == site_prefix.trim_end_matches('/');
+        let exists = is_site_root || relative.is_some_and(|relative| {
+            let file = dist.join(relative);
+            relative.is_empty() || relative.ends_with('/') || file.is_file() || file.is_dir()
+        });
+        if !exists && !missing.contains(&url) {
+            missing.push(url);
+        }
+    }
+
+    if missing.is_empty() {
+        return Ok(())
+    }
+    Err(Error::new(ErrorKind::NotFound, format!(
+        "index.html references {} missing file(s) when served under {}:\n  {}", missing.len(), site_prefix, missing.join("\n  "))))
+}
+
+/// Inserts `snippet` just before the closing `</head>` tag of `html`, or at the end if there is none. 
+/// Pages that already contain the snippet are returned unchanged, so injecting it again on a later deploy doesn't duplicate it.
+pub fn inject_head(html: &str, snippet: &str) -> String {
+    let snippet = snippet.trim();
+    if snippet.is_empty() || html.contains(snippet) {
+        return html.to_string()
+    }
+
+    match html.to_ascii_lowercase().find("</head>") {
+        Some(idx) => format!("{}{}\n{}", &html[..idx], snippet, &html[idx..]),
+        None => format!("{}\n{}\n", html.trim_end(), snippet),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_references_skip_page_links() {
+        let html = "<link rel=\"stylesheet\" href=\"/style.css\"><script src=/app.js></script>\
+            <a href=\"/\">Home</a><a href=\"/about\">About</a><img srcset=\"a.png 2x\" src='a.png'><video><source src=\"v.mp4\"></video>";
+        assert_eq!(asset_references(html), ["/style.css", "/app.js", "a.png", "v.mp4"]);
+    }
+
+    #[test]
+    fn inject_head_inserts_the_snippet_once() {
+        let snippet = "\n<meta name=\"robots\" content=\"noindex\">\n";
+        let html = "<html><head><title>Site</title></head><body></body></html>";
+        let injected = inject_head(html, snippet);
+        assert_eq!(injected, "<html><head><title>Site</title><meta name=\"robots\" content=\"noindex\">\n</head><body></body></html>");
+        assert_eq!(inject_head(&injected, snippet), injected);
+
+        let injected = inject_head("<HTML><HEAD></HEAD></HTML>", snippet);
+        assert_eq!(injected, "<HTML><HEAD><meta name=\"robots\" content=\"noindex\">\n</HEAD></HTML>");
+        assert_eq!(inject_head(&injected, snippet), injected);
+
+        let injected = inject_head("<p>No head</p>\n", snippet);
+        assert_eq!(injected, "<p>No head</p>\n<meta name=\"robots\" content=\"noindex\">\n");
+        assert_eq!(inject_head(&injected, snippet), injected);
+    }
+
+    #[test]
+    fn verify_links_accepts_the_site_root_and_directories() {
+        let dist = std::env::temp_dir().join(format!("trunk-ghpages-verify-{}", std::process::id()));
+        std::fs::create_dir_all(dist.join("assets")).unwrap();
+        std::fs::write(dist.join("app.js"), "").unwrap();
+        let html = "<a href=\"/\"></a><a href=\"/settings\"></a><script src=\"/remote/app.js\"></script>\
+            <link rel=\"prefetch\" href=\"/remote/\"><link rel=\"prefetch\" href=\"/remote/assets/\">";
+        let res = verify_links(html, &dist, "remote");
+        let missing = verify_links("<script src=\"/remote/missing.js\"></script><img src=\"/app.js\">", &dist, "remote");
+        std::fs::remove_dir_all(&dist).unwrap();
+
+        assert!(res.is_ok(), "{:?}", res);
+        let message = missing.unwrap_err().to_string();
+        assert!(message.contains("/remote/missing.js") && message.contains("\n  /app.js"), "{}", message);
+    }
+}
@@ -0,0 +1,34 @@
+/// Formats `s` as a quoted JSON string, escaping any characters JSON doesn't allow
+///
+/// # Example:
+/// ```ignore
+/// assert_eq!(json::string("a \"b\""), "\"a \\\"b\\\"\"");
+/// ```
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_escapes_quotes_and_control_characters() {
+        assert_eq!(string("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(string("C:\\dist\n\t\u{1}"), "\"C:\\\\dist\\n\\t\\u0001\"");
+    }
+}
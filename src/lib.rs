@@ -0,0 +1,1602 @@
+pub mod cli;
+pub mod config;
+mod diff;
+pub mod html;
+mod json;
+pub mod log;
+pub mod merge;
+pub mod push;
+pub mod remote;
+pub mod rewrite;
+mod sha256;
+pub mod smoke;
+pub mod stage;
+pub mod worktree;
+
+use std::{env, error, ffi::OsStr, fmt, fs::{create_dir, metadata, read, read_to_string, remove_dir_all, remove_file, write, OpenOptions}, io::{stderr, Error, ErrorKind, Write}, path::{Path, PathBuf}, process::{self, Command}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use cli::{Options, OutputFormat};
+use config::{ConfigFile, Settings};
+use log::redact;
+use push::{check_git_version, check_remote_access, commit_date, delete_remote_branches, ensure_success, glob_match, head_sha, last_deployed_source, output_step, push_dir_to_branch, remote_branch_exists, remote_branches, remove_stray_git, source_trailers, Commit};
+use remote::{find_repo_root, get_remote_origin, github_actions_remote, remote_from_env, validate_pages_url_template, with_token, RemoteUrl};
+use rewrite::{update_indexhtml, update_nested_html};
+
+/// Everything needed to deploy a site with [deploy]
+#[derive(Debug, Default)]
+pub struct DeployConfig {
+    /// The project directory, containing dist and the source repo
+    pub project_dir: PathBuf,
+    /// The same options the command line exposes, with the config file already applied
+    pub options: Options,
+}
+
+/// The result of a successful deploy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeployOutcome {
+    /// The path the site is served under, e.g. `MyRepo`, or `MyRepo/v1.2.0` when deploying into a subdirectory
+    pub repo_name: String,
+    /// The branch deployed to
+    pub branch: String,
+    /// The commit pushed to the branch (or only created, for offline deploys), or None if nothing was committed
+    pub commit_sha: Option<String>,
+    /// The URL the site is served at
+    pub pages_url: String,
+    /// Whether the branch was changed by the deploy, which is false for dry runs, offline deploys and deploys without any changes
+    pub changed: bool,
+    /// Whether the deploy was skipped by `--since-last-deploy`, since the source didn't change
+    pub skipped: bool,
+    /// The number of files deployed, or 0 if the deploy was skipped
+    pub file_count: usize,
+    /// The git commands run by the deploy, in order
+    pub steps: Vec<log::Step>,
+    /// The time spent in each phase of the deploy, like `"rewrite"` or `"push"`, in the order they started
+    pub timings: Vec<(&'static str, Duration)>,
+}
+
+impl DeployOutcome {
+    /// Formats the timings as a table, for the summary printed after a deploy
+    pub fn timings_table(&self) -> String {
+        let width = self.timings.iter().map(|(phase, _)| phase.len()).max().unwrap_or(0);
+        self.timings.iter()
+            .map(|(phase, duration)| format!("  {:<width$}  {:>6}ms\n", phase, duration.as_millis()))
+            .collect()
+    }
+
+    /// Formats the outcome as a JSON object, for `--format json`, with the timings in milliseconds. 
+    /// The steps are only included when `with_steps` is set
+    pub fn to_json(&self, with_steps: bool) -> String {
+        let mut fields = vec![
+            format!("  \"repo_name\": {}", json::string(&self.repo_name)),
+            format!("  \"branch\": {}", json::string(&self.branch)),
+            format!("  \"commit_sha\": {}", self.commit_sha.as_deref().map_or("null".to_string(), json::string)),
+            format!("  \"pages_url\": {}", json::string(&self.pages_url)),
+            format!("  \"changed\": {}", self.changed),
+            format!("  \"skipped\": {}", self.skipped),
+            format!("  \"file_count\": {}", self.file_count),
+            format!("  \"timings\": {{{}}}", self.timings.iter()
+                .map(|(phase, duration)| format!("{}: {}", json::string(phase), duration.as_millis()))
+                .collect::<Vec<String>>()
+                .join(", ")),
+        ];
+        if with_steps {
+            let steps: Vec<String> = self.steps.iter()
+                .map(|step| format!("    {{\"command\": {}, \"duration_ms\": {}, \"exit_code\": {}}}",
+                    json::string(&step.command), step.duration.as_millis(), step.exit_code.map_or("null".to_string(), |code| code.to_string())))
+                .collect();
+            fields.push(format!("  \"steps\": [\n{}\n  ]", steps.join(",\n")));
+        }
+        redact(&format!("{{\n{}\n}}\n", fields.join(",\n")))
+    }
+}
+
+/// The ways a deploy can fail, by the step that failed
+#[derive(Debug)]
+pub enum DeployError {
+    /// Running `trunk build` failed
+    Build(Error),
+    /// The remote to deploy to couldn't be found, or isn't usable
+    Remote(Error),
+    /// The options are invalid, or a safety check failed
+    Config(Error),
+    /// Reading, rewriting or checking the built site failed
+    Site(Error),
+    /// Committing or pushing the site failed
+    Push(Error),
+    /// The site was committed (and pushed, unless `--commit-only` or `--offline` was given), 
+    /// but warnings were given along the way and `--strict` is set
+    Strict(Error),
+    /// The site was pushed, but isn't served correctly according to `--smoke-test`
+    SmokeTest(Error),
+}
+
+impl DeployError {
+    /// The underlying error
+    pub fn inner(&self) -> &Error {
+        match self {
+            DeployError::Build(e) | DeployError::Remote(e) | DeployError::Config(e) | DeployError::Site(e) | DeployError::Push(e)
+                | DeployError::Strict(e) | DeployError::SmokeTest(e) => e,
+        }
+    }
+
+    /// Takes the underlying error
+    pub fn into_inner(self) -> Error {
+        match self {
+            DeployError::Build(e) | DeployError::Remote(e) | DeployError::Config(e) | DeployError::Site(e) | DeployError::Push(e)
+                | DeployError::Strict(e) | DeployError::SmokeTest(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for DeployError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner().fmt(f)
+    }
+}
+
+impl error::Error for DeployError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.inner())
+    }
+}
+
+impl From<DeployError> for Error {
+    fn from(e: DeployError) -> Error {
+        e.into_inner()
+    }
+}
+
+/// Builds (with `--build`), rewrites and pushes the site of the project in `config.project_dir`, 
+/// just like running trunk-ghpages in that directory. 
+/// 
+/// The steps, timings and warnings (for `--strict`) of the deploy are forgotten again when it returns, 
+/// so every call is reported and checked on its own, even when deploying several times from the same process.
+/// 
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// let options = Options::parse(["--branch".to_string(), "pages".to_string()])?;
+/// let outcome = deploy(DeployConfig { project_dir: std::env::current_dir()?, options })?;
+/// println!("Deployed {} to {}", outcome.commit_sha.unwrap_or_default(), outcome.pages_url);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn deploy(config: DeployConfig) -> Result<DeployOutcome, DeployError> {
+    with_own_log(|| run_deploy(config))
+}
+
+/// Runs `f`, then forgets the steps, timings and warnings it recorded, so the global log doesn't grow with every call. 
+/// What was recorded before, like the config timing of the command line, is kept and seen by `f`.
+fn with_own_log<T>(f: impl FnOnce() -> T) -> T {
+    let outer = log::snapshot();
+    let res = f();
+    log::restore(outer);
+    res
+}
+
+/// Does the work of [deploy], recording its steps, timings and warnings in the global log
+fn run_deploy(config: DeployConfig) -> Result<DeployOutcome, DeployError> {
+    let DeployConfig { project_dir: cwd, options: opts } = config;
+    // a site that wasn't built is the most common mistake, so it's reported before any git command runs. 
+    // Sites built with --build or extracted from an archive are checked once they exist
+    if !opts.build && opts.archive.is_none() && !opts.no_index_rewrite {
+        check_index_exists(&cwd.join(opts.dist())).map_err(DeployError::Site)?;
+    }
+    check_git_version(&opts).map_err(DeployError::Config)?;
+    let remote_origin = resolve_remote(&cwd, &opts)?;
+
+    // the build is skipped as well, since it's often the slowest part
+    if opts.since_last_deploy && !opts.offline {
+        if let Some(outcome) = skip_unchanged_source(&cwd, &remote_origin, &opts)? {
+            return Ok(outcome)
+        }
+    }
+
+    if opts.build {
+        let building = Instant::now();
+        trunk_build(&cwd, &opts).map_err(DeployError::Build)?;
+        log::add_timing("build", building.elapsed());
+    }
+
+    // an archive is extracted into a temporary dist, which is removed again after deploying
+    match &opts.archive {
+        Some(archive) => {
+            let extract_dir = create_temp_dir(&opts).map_err(DeployError::Site)?;
+            let res = extract_archive(&cwd.join(archive), &extract_dir).map_err(DeployError::Site)
+                .and_then(|dist| deploy_dist(&cwd, &dist, &remote_origin, &opts));
+            remove_dir_all(&extract_dir).map_err(DeployError::Site)?;
+            res
+        }
+        None => deploy_dist(&cwd, &cwd.join(opts.dist()), &remote_origin, &opts),
+    }
+}
+
+/// Deploys the site to every `--target` one after the other, for mirroring it to several repos or branches, 
+/// and returns the result of each target along with its name. With `--fail-fast`, the targets after the first failure are skipped. 
+/// 
+/// Each target gets its own copy of dist, so the paths are prefixed with the repo name of that target, 
+/// and is deployed with [deploy], so the steps, timings and warnings (for `--strict`) of each target are kept apart. 
+/// With `--build`, the site is built once before deploying to any of them.
+///
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// let options = Options::parse(["--target".to_string(), "git@github.com:a/site.git:gh-pages".to_string()])?;
+/// for (target, result) in deploy_targets(DeployConfig { project_dir: std::env::current_dir()?, options }) {
+///     println!("{}: {}", target, result.is_ok());
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn deploy_targets(config: DeployConfig) -> Vec<(String, Result<DeployOutcome, DeployError>)> {
+    let DeployConfig { project_dir: cwd, options: opts } = config;
+    if opts.build {
+        if let Err(e) = trunk_build(&cwd, &opts) {
+            return vec![("trunk build".to_string(), Err(DeployError::Build(e)))]
+        }
+    }
+
+    let mut results = Vec::new();
+    for (remote, branch) in &opts.targets {
+        let mut target_opts = Options { repo: Some(remote.clone()), branch: Some(branch.clone()), targets: Vec::new(), build: false, ..opts.clone() };
+        let res = match &opts.archive {
+            // every target extracts the archive anew anyway
+            Some(_) => deploy(DeployConfig { project_dir: cwd.clone(), options: target_opts }),
+            None => create_temp_dir(&opts).map_err(DeployError::Site).and_then(|copy_dir| {
+                let res = stage::copy_deployed_files(&cwd.join(opts.dist()), &copy_dir, &opts).map_err(DeployError::Site)
+                    .and_then(|()| {
+                        target_opts.dist = Some(copy_dir.to_string_lossy().into_owned());
+                        deploy(DeployConfig { project_dir: cwd.clone(), options: target_opts })
+                    });
+                remove_dir_all(&copy_dir).map_err(DeployError::Site)?;
+                res
+            }),
+        };
+        let failed = res.is_err();
+        results.push((format!("{}:{}", redact(remote), branch), res));
+        if failed && opts.fail_fast {
+            break
+        }
+    }
+    results
+}
+
+/// Checks whether HEAD of the source repo at `cwd` is the commit the branch was last deployed from, for `--since-last-deploy`, 
+/// and if so returns the outcome of skipping the deploy
+fn skip_unchanged_source(cwd: &Path, remote_origin: &str, opts: &Options) -> Result<Option<DeployOutcome>, DeployError>{
+    let source = head_sha(cwd, opts).map_err(DeployError::Config)?;
+    let push_url = push_url(remote_origin, opts)?;
+    if last_deployed_source(&push_url, opts.branch(), opts).map_err(DeployError::Remote)? != Some(source) {
+        return Ok(None)
+    }
+
+    if opts.format == OutputFormat::Text {
+        println!("Source unchanged since last deploy; skipping.");
+    }
+    let subdir = opts.subdir().map_err(DeployError::Config)?;
+    let (site_path, pages_url) = site_location(&RemoteUrl::parse(remote_origin), subdir, opts);
+    Ok(Some(DeployOutcome {
+        repo_name: site_path,
+        branch: opts.branch().to_string(),
+        commit_sha: None,
+        pages_url,
+        changed: false,
+        skipped: true,
+        file_count: 0,
+        steps: log::steps(),
+        timings: log::timings(),
+    }))
+}
+
+/// Deletes the branches of the remote matching the glob `pattern`, for `--prune`, and returns how many were deleted. 
+/// With `--dry-run` the matching branches are only listed.
+///
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// let options = Options::parse(["--yes".to_string()])?;
+/// let pruned = prune(DeployConfig { project_dir: std::env::current_dir()?, options }, "preview-*")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn prune(config: DeployConfig, pattern: &str) -> Result<usize, DeployError> {
+    with_own_log(|| run_prune(config, pattern))
+}
+
+fn run_prune(config: DeployConfig, pattern: &str) -> Result<usize, DeployError> {
+    let DeployConfig { project_dir: cwd, options: opts } = config;
+    check_git_version(&opts).map_err(DeployError::Config)?;
+    let remote_origin = resolve_remote(&cwd, &opts)?;
+    let push_url = push_url(&remote_origin, &opts)?;
+
+    let mut branches: Vec<String> = remote_branches(&cwd, &push_url, &opts).map_err(DeployError::Remote)?
+        .into_iter()
+        .filter(|branch| glob_match(pattern, branch))
+        .collect();
+    // the site itself is never pruned by accident
+    if let Some(idx) = branches.iter().position(|branch| branch == opts.branch()).filter(|_| !opts.force) {
+        log::warn(format!("Not pruning '{}', since it is the branch deployed to. Pass --force to prune it anyway", branches.remove(idx)));
+    }
+
+    if branches.is_empty() || opts.dry_run {
+        match branches.is_empty() {
+            true => println!("No branches of {} match '{}'", redact(&remote_origin), pattern),
+            false => println!("Dry run: would prune {} branch(es) of {}: {}", branches.len(), redact(&remote_origin), branches.join(", ")),
+        }
+        return Ok(0)
+    }
+    delete_remote_branches(&cwd, &push_url, &branches, &opts).map_err(DeployError::Push)?;
+    Ok(branches.len())
+}
+
+/// Removes the `.git` directory a crashed deploy left behind in the dist of the project, for `--repair`. 
+/// Returns whether there was one to remove.
+///
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// let options = Options::parse(["--yes".to_string()])?;
+/// let repaired = repair(DeployConfig { project_dir: std::env::current_dir()?, options })?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn repair(config: DeployConfig) -> Result<bool, DeployError> {
+    let DeployConfig { project_dir: cwd, options: opts } = config;
+    let dist = cwd.join(opts.dist());
+    // the .git of the source repo itself is never a leftover
+    check_dist_outside_repo(&cwd, &dist).map_err(DeployError::Config)?;
+    remove_stray_git(&dist, &opts).map_err(DeployError::Site)
+}
+
+/// Fills in the settings of `opts` that weren't given on the command line, which takes precedence over everything else. 
+/// The other sources follow in this order: the environment variables read into `env`, the `[env.<name>]` table of the config file 
+/// selected with `--env`, the rest of the config file, and finally the `[tool.trunk-ghpages]` table of the `Trunk.toml` in `dir`.
+///
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, config::Settings, *};
+/// let mut options = Options::parse(std::env::args().skip(1))?;
+/// apply_settings(&mut options, &std::env::current_dir()?, &Settings::from_env()?)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn apply_settings(opts: &mut Options, dir: &Path, env: &Settings) -> Result<(), Error> {
+    opts.apply(env)?;
+    let config = ConfigFile::load(dir)?;
+    if let Some(name) = opts.env.clone() {
+        opts.apply(config.env(&name)?)?;
+    }
+    opts.apply(&config.settings)?;
+    opts.apply(&ConfigFile::load_trunk_toml(dir)?)
+}
+
+/// Formats the configuration a deploy of the project would use as a JSON object, for `--print-config`: 
+/// the remote, branch, dist and the other settings after combining the command line, environment variables and config file, 
+/// plus the subdirectory and Pages URL following from them. The token is only shown as `***`.
+///
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// let config = effective_config(&DeployConfig { project_dir: std::env::current_dir()?, options: Options::default() })?;
+/// print!("{}", config);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn effective_config(config: &DeployConfig) -> Result<String, DeployError> {
+    let DeployConfig { project_dir: cwd, options: opts } = config;
+    let remote_origin = resolve_remote(cwd, opts)?;
+    let subdir = opts.subdir().map_err(DeployError::Config)?;
+    let (site_path, pages_url) = site_location(&RemoteUrl::parse(&remote_origin), subdir, opts);
+
+    let optional = |value: Option<&str>| value.map_or("null".to_string(), json::string);
+    let fields = [
+        ("project_dir", json::string(&cwd.display().to_string())),
+        ("env", optional(opts.env.as_deref())),
+        ("remote", json::string(&remote_origin)),
+        ("token", optional(opts.token.as_ref().map(|_| "***"))),
+        ("branch", json::string(opts.branch())),
+        ("dist", json::string(opts.dist())),
+        ("archive", optional(opts.archive.as_deref())),
+        ("subdir", optional(subdir)),
+        ("cname", optional(opts.cname.as_deref())),
+        ("provider", json::string(&opts.provider().to_string())),
+        ("site_path", json::string(&site_path)),
+        ("pages_url", json::string(&pages_url)),
+    ];
+    let fields: Vec<String> = fields.iter().map(|(key, value)| format!("  {}: {}", json::string(key), value)).collect();
+    if let Some(token) = &opts.token {
+        log::add_secret(token);
+    }
+    Ok(redact(&format!("{{\n{}\n}}\n", fields.join(",\n"))))
+}
+
+/// Checks that the remote of the project can be accessed with the configured credentials (such as `--token` or an SSH key) 
+/// without modifying anything, for `--check-auth`. Returns the remote URL, with any credentials masked.
+///
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// let remote = check_auth(DeployConfig { project_dir: std::env::current_dir()?, options: Options::default() })?;
+/// println!("Can access {}", remote);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn check_auth(config: DeployConfig) -> Result<String, DeployError> {
+    let DeployConfig { project_dir: cwd, options: opts } = config;
+    with_own_log(|| {
+        check_git_version(&opts).map_err(DeployError::Config)?;
+        let remote_origin = resolve_remote(&cwd, &opts)?;
+        let push_url = push_url(&remote_origin, &opts)?;
+        check_remote_access(&cwd, &push_url, &opts).map_err(DeployError::Remote)?;
+        Ok(redact(&remote_origin))
+    })
+}
+
+/// A single check of [preflight], with a description of what was found on success, or the reason it failed
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<String, Error>,
+}
+
+/// Checks everything about the deploy that can be checked locally, for `--check`: git, the remote and the site path 
+/// derived from it, the options and `dist`. Nothing is rewritten, committed or pushed, and nothing is sent to the remote.
+/// 
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// let checks = preflight(&DeployConfig { project_dir: std::env::current_dir()?, options: Options::default() });
+/// let ready = checks.iter().all(|check| check.result.is_ok());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn preflight(config: &DeployConfig) -> Vec<Check> {
+    with_own_log(|| run_preflight(config))
+}
+
+fn run_preflight(config: &DeployConfig) -> Vec<Check> {
+    let DeployConfig { project_dir: cwd, options: opts } = config;
+    let mut checks = Vec::new();
+
+    let git_version = output_step(Command::new(opts.git_bin()).arg("--version"))
+        .map_err(|e| Error::new(e.kind(), format!("Could not run {}: {}", opts.git_bin(), e)))
+        .and_then(|output| check_git_version(opts).map(|()| String::from_utf8_lossy(&output.stdout).trim().to_string()));
+    checks.push(Check { name: "git", result: git_version });
+
+    match resolve_remote(cwd, opts) {
+        Ok(remote_origin) => {
+            checks.push(Check { name: "remote", result: Ok(redact(&remote_origin)) });
+            let remote = RemoteUrl::parse(&remote_origin);
+            let site = match opts.force {
+                true => Ok(()),
+                false => opts.provider().check_host(&remote),
+            }
+            .and_then(|()| opts.subdir())
+            .and_then(|subdir| subdir.map(merge::validate_subdir).transpose())
+            .map(|subdir| site_location(&remote, subdir, opts))
+            .map(|(site_path, pages_url)| format!("served under /{}/ at {}", site_path, pages_url));
+            checks.push(Check { name: "site path", result: site });
+        }
+        Err(e) => checks.push(Check { name: "remote", result: Err(e.into_inner()) }),
+    }
+
+    let options = opts.pages_url_template.as_deref().map_or(Ok(()), validate_pages_url_template)
+        .and_then(|()| match (&opts.token, opts.credential_helper, opts.root_redirect, opts.subdir()?) {
+            (None, true, _, _) => Err(credential_helper_error()),
+            (_, _, true, None) => Err(root_redirect_error()),
+            _ => Ok("valid".to_string()),
+        });
+    checks.push(Check { name: "options", result: options });
+
+    let dist = match &opts.archive {
+        Some(archive) => match cwd.join(archive).is_file() {
+            true => Ok(format!("archive {} exists", archive)),
+            false => Err(Error::new(ErrorKind::NotFound, format!("The archive {} doesn't exist", cwd.join(archive).display()))),
+        },
+        None => {
+            let dist = cwd.join(opts.dist());
+            check_dist_outside_repo(cwd, &dist).and_then(|()| match (dist.is_dir(), metadata(dist.join("index.html"))) {
+                (false, _) => Err(Error::new(ErrorKind::NotFound, format!("{} is not a directory. Build the site with `trunk build` first", dist.display()))),
+                (true, Err(_)) => Err(Error::new(ErrorKind::NotFound, format!("{} has no index.html", dist.display()))),
+                (true, Ok(index)) if index.len() == 0 => Err(Error::new(ErrorKind::InvalidData, format!("{} is empty", dist.join("index.html").display()))),
+                (true, Ok(_)) => Ok(format!("{} has an index.html", dist.display())),
+            })
+        }
+    };
+    checks.push(Check { name: "dist", result: dist });
+
+    checks
+}
+
+// in checkout-less CI jobs there is no .git/config, so the remote can come from the environment instead
+fn resolve_remote(cwd: &Path, opts: &Options) -> Result<String, DeployError>{
+    Ok(match (&opts.repo, &opts.remote_url_env) {
+        (Some(repo), _) => repo.clone(),
+        (None, Some(var)) => remote_from_env(var).map_err(DeployError::Remote)?,
+        (None, None) => match find_repo_root(cwd).ok_or_else(|| no_repo_error(cwd)).and_then(get_remote_origin) {
+            Ok(remote_origin) => remote_origin,
+            Err(e) => github_actions_remote().ok_or(DeployError::Remote(e))?,
+        },
+    })
+}
+
+// with --credential-helper the token is handed to git by a helper instead, so it never ends up in the remote URL
+fn push_url(remote_origin: &str, opts: &Options) -> Result<String, DeployError>{
+    // the pushes and everything printed about them go through here, so the token is known to be masked in time
+    if let Some(token) = &opts.token {
+        log::add_secret(token);
+    }
+    match (&opts.token, opts.credential_helper) {
+        (Some(token), false) => with_token(remote_origin, token).map_err(DeployError::Remote),
+        (None, true) => Err(DeployError::Config(credential_helper_error())),
+        _ => Ok(remote_origin.to_string()),
+    }
+}
+
+fn credential_helper_error() -> Error{
+    Error::new(ErrorKind::InvalidInput, "--credential-helper requires a --token to provide")
+}
+
+fn root_redirect_error() -> Error{
+    Error::new(ErrorKind::InvalidInput, "--root-redirect needs --mount, --target-subdir or --version-dir to redirect to")
+}
+
+fn no_repo_error(dir: &Path) -> Error{
+    Error::new(ErrorKind::NotFound, format!("Could not find a git repo in {} or any of its parents. \
+        Run trunk-ghpages inside of the repo, or pass the URL of the repo to deploy to with --repo <url>", dir.display()))
+}
+
+/// Finds the project directory that `dir` is in, so trunk-ghpages can be run from any subdirectory of the project: 
+/// the closest of `dir` and its parents containing a `Trunk.toml`, up to the root of the git repo, or else the root of the repo. 
+/// Outside of a git repo, such as in checkout-less deploys with --repo, this is `dir` itself.
+pub fn find_project_dir(dir: &Path) -> PathBuf{
+    let Some(repo_root) = find_repo_root(dir) else {
+        return dir.to_path_buf()
+    };
+    dir.ancestors()
+        .take_while(|parent| parent.starts_with(repo_root))
+        .find(|parent| parent.join("Trunk.toml").is_file())
+        .unwrap_or(repo_root)
+        .to_path_buf()
+}
+
+/// Rewrites the site in `dist` and deploys it to `remote_origin`. 
+/// If the deploy fails before the branch is updated, `index.html` is restored to how it was before the rewrite. 
+/// Once the branch is updated, the rewritten `index.html` is what is deployed, so it's kept even if a later check fails.
+/// The branch notice isn't part of the built site, so it's removed from dist again after every deploy, whether it succeeded or not.
+fn deploy_dist(cwd: &Path, dist: &Path, remote_origin: &str, opts: &Options) -> Result<DeployOutcome, DeployError>{
+    let index_path = dist.join("index.html");
+    let original_index = read(&index_path).ok();
+    let notice_path = dist.join(BRANCH_NOTICE_FILE);
+    let writes_notice = (opts.branch_notice || opts.pages_branch_readme) && !notice_path.exists();
+
+    let res = match deploy_site(cwd, dist, remote_origin, opts) {
+        Ok(outcome) => finish_deploy(dist, outcome, opts),
+        Err(e) => {
+            if let Some(original) = original_index.filter(|original| !read(&index_path).is_ok_and(|index| index == *original)) {
+                if let Err(restore_error) = write(&index_path, original) {
+                    log::warn(format!("Could not restore {} after the failed deploy: {}", index_path.display(), restore_error));
+                }
+            }
+            Err(e)
+        }
+    };
+
+    if writes_notice && notice_path.exists() {
+        if let Err(e) = remove_file(&notice_path) {
+            log::warn(format!("Could not remove the branch notice {} after deploying: {}", notice_path.display(), e));
+        }
+    }
+    res
+}
+
+/// Does the work of `deploy_dist` up to and including updating the branch, leaving the rewritten `index.html` in place
+fn deploy_site(cwd: &Path, dist: &Path, remote_origin: &str, opts: &Options) -> Result<DeployOutcome, DeployError>{
+    if !opts.no_index_rewrite {
+        check_index_exists(dist).map_err(DeployError::Site)?;
+    }
+    let remote = RemoteUrl::parse(remote_origin);
+    let push_url = push_url(remote_origin, opts)?;
+    if let Some(template) = &opts.pages_url_template {
+        validate_pages_url_template(template).map_err(DeployError::Config)?;
+    }
+    check_dist_outside_repo(cwd, dist).map_err(DeployError::Config)?;
+    if !opts.force {
+        opts.provider().check_host(&remote).map_err(DeployError::Remote)?;
+        check_not_on_branch(cwd, opts).map_err(DeployError::Config)?;
+    }
+    // a typo in --branch shouldn't create a new public branch
+    if opts.require_existing_branch && !opts.offline && !remote_branch_exists(cwd, &push_url, opts.branch(), opts).map_err(DeployError::Remote)? {
+        return Err(DeployError::Config(Error::new(ErrorKind::NotFound, format!(
+            "Branch '{}' does not exist on {}, and --require-existing-branch was given", opts.branch(), redact(remote_origin)))))
+    }
+    let subdir = opts.subdir().and_then(|subdir| subdir.map(merge::validate_subdir).transpose()).map_err(DeployError::Config)?;
+    if opts.eject.is_some() && opts.commit_only {
+        return Err(DeployError::Config(Error::new(ErrorKind::InvalidInput, "--eject and --commit-only can't be used together")))
+    }
+    if let Some(eject) = opts.eject.as_deref().map(|dir| cwd.join(dir)).filter(|dir| dir.exists()) {
+        return Err(DeployError::Config(Error::new(ErrorKind::AlreadyExists, format!(
+            "{} already exists, pick a new directory for --eject", eject.display()))))
+    }
+    // only the temporary repo of a whole-branch deploy is committed with plumbing
+    if opts.fast && (subdir.is_some() || opts.commit_only || opts.eject.is_some()) {
+        return Err(DeployError::Config(Error::new(ErrorKind::InvalidInput,
+            "--fast only works when deploying to the whole branch, not with --target-subdir, --version-dir, --mount, --commit-only or --eject")))
+    }
+    if opts.root_redirect && subdir.is_none() {
+        return Err(DeployError::Config(root_redirect_error()))
+    }
+    let mut message = commit_message(opts).map_err(DeployError::Config)?;
+    let (site_path, pages_url) = site_location(&remote, subdir, opts);
+
+    let rewriting = Instant::now();
+    prepare_site(cwd, dist, &site_path, opts).map_err(DeployError::Site)?;
+    log::add_timing("rewrite", rewriting.elapsed());
+    // warnings about the site are checked before pushing it, and the ones given while pushing after
+    log::check_warnings(opts.strict).map_err(DeployError::Site)?;
+
+    let mut outcome = DeployOutcome {
+        repo_name: site_path,
+        branch: opts.branch().to_string(),
+        commit_sha: None,
+        pages_url,
+        changed: false,
+        skipped: false,
+        file_count: stage::deployed_files(dist, opts).map_err(DeployError::Site)?.len(),
+        steps: Vec::new(),
+        timings: log::timings(),
+    };
+
+    // the JSON output is printed by the caller instead
+    let text = opts.format == OutputFormat::Text;
+    if opts.dry_run {
+        if text {
+            println!("Dry run: would deploy {} to branch '{}' of {}", dist.display(), opts.branch(), redact(remote_origin));
+        }
+        return Ok(outcome)
+    }
+
+    // --since-last-deploy reads the trailer of the last deploy
+    if opts.source_trailer || opts.since_last_deploy {
+        message.push_str(&format!("\n\n{}", source_trailers(cwd, opts).map_err(DeployError::Push)?));
+    }
+    let commit = Commit {
+        message,
+        date: commit_date(cwd, opts).map_err(DeployError::Push)?,
+    };
+
+    if let Some(eject) = &opts.eject {
+        let repo_root = find_repo_root(cwd).ok_or_else(|| no_repo_error(cwd)).map_err(DeployError::Config)?;
+        let worktree = cwd.join(eject);
+        let staging = Instant::now();
+        worktree::add_worktree(repo_root, &worktree, opts.branch(), opts)
+            .and_then(|()| worktree::stage_worktree(&worktree, dist, subdir, opts))
+            .map_err(DeployError::Push)?;
+        log::add_timing("staging", staging.elapsed());
+        outcome.steps = log::steps();
+        outcome.timings = log::timings();
+        if text {
+            println!("Set up branch '{}' in the worktree {} with the site staged. To finish the deploy, run:\n\n\
+                cd {}\n\
+                git commit -m {}\n\
+                git push origin {}\n\n\
+                The worktree is left in place, remove it with `git worktree remove {}` when you're done",
+                opts.branch(), worktree.display(), worktree.display(), shell_quote(&commit.message), opts.branch(), worktree.display());
+        }
+        return Ok(outcome)
+    }
+
+    if opts.commit_only {
+        let repo_root = find_repo_root(cwd).ok_or_else(|| no_repo_error(cwd)).map_err(DeployError::Config)?;
+        outcome.commit_sha = worktree::commit_to_branch(repo_root, dist, opts.branch(), subdir, &commit, opts).map_err(DeployError::Push)?;
+        outcome.steps = log::steps();
+        outcome.timings = log::timings();
+        if let (Some(commit_sha), true) = (&outcome.commit_sha, text) {
+            println!("Committed {} to branch '{}' of the source repo. Push it with `git push origin {}`", commit_sha, opts.branch(), opts.branch());
+        }
+        return Ok(outcome)
+    }
+
+    outcome.commit_sha = match subdir {
+        Some(subdir) => merge::push_dir_to_subdir(&push_url, dist, opts.branch(), subdir, &commit, opts),
+        None => push_dir_to_branch(&push_url, dist, opts.branch(), &commit, opts).map(Some),
+    }.map_err(DeployError::Push)?;
+    outcome.changed = outcome.commit_sha.is_some() && !opts.offline;
+    outcome.steps = log::steps();
+    outcome.timings = log::timings();
+    if let (Some(commit_sha), true, true) = (&outcome.commit_sha, opts.offline, text) {
+        println!("Offline: committed {} for branch '{}' of {}, but didn't push it", commit_sha, opts.branch(), redact(remote_origin));
+    }
+    Ok(outcome)
+}
+
+/// Does the checks of a deploy after the branch was updated by `deploy_site`: the warnings given along the way with `--strict`, 
+/// and for pushed sites the `--smoke-test`, then writes the job summary of GitHub Actions. 
+/// Nothing in dist is restored if these fail, since the branch has the site already.
+fn finish_deploy(dist: &Path, mut outcome: DeployOutcome, opts: &Options) -> Result<DeployOutcome, DeployError>{
+    log::check_warnings(opts.strict).map_err(DeployError::Strict)?;
+    let pushed = !opts.dry_run && !opts.offline && !opts.commit_only && opts.eject.is_none();
+    if !pushed {
+        return Ok(outcome)
+    }
+
+    if opts.smoke_test {
+        let testing = Instant::now();
+        let index_html = read_to_string(dist.join("index.html")).map_err(DeployError::SmokeTest)?;
+        let mut urls = vec![outcome.pages_url.clone()];
+        urls.extend(smoke::asset_urls(&index_html, &outcome.pages_url));
+        let report = smoke::smoke_test(&urls, Duration::from_secs(opts.smoke_timeout())).map_err(DeployError::SmokeTest)?;
+        if opts.format == OutputFormat::Text && !log::is_quiet() {
+            println!("Smoke test passed:\n{}", report);
+        }
+        log::add_timing("smoke test", testing.elapsed());
+        outcome.timings = log::timings();
+    }
+
+    if let Err(e) = write_step_summary(&outcome) {
+        log::warn(format!("Could not write the GitHub Actions job summary: {}", e));
+    }
+
+    Ok(outcome)
+}
+
+/// Fills in the `--message` template of the deploy commit message, `Update {branch}` by default, 
+/// replacing `{branch}` with the branch and `{build}` with the `--build-number`
+fn commit_message(opts: &Options) -> Result<String, Error>{
+    let template = opts.message.as_deref().unwrap_or("Update {branch}");
+    let build = match (template.contains("{build}"), opts.build_number) {
+        (true, None) => return Err(Error::new(ErrorKind::InvalidInput, "The --message template uses {build}, but no --build-number was given")),
+        (_, build) => build.map(|build| build.to_string()).unwrap_or_default(),
+    };
+    Ok(template.replace("{branch}", opts.branch()).replace("{build}", &build))
+}
+
+/// Quotes `arg` for a POSIX shell, for commands printed for the user to run
+fn shell_quote(arg: &str) -> String{
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Gets the path the site is served under and its Pages URL, for a site deployed to `remote` (into `subdir`, if given). 
+/// The path is empty for sites served at the root of a `--cname` domain.
+fn site_location(remote: &RemoteUrl, subdir: Option<&str>, opts: &Options) -> (String, String){
+    // `my-app/` is served under the same path as `my-app`
+    let subdir = subdir.map(|subdir| subdir.trim_matches('/')).filter(|subdir| !subdir.is_empty());
+    let provider = opts.provider();
+    let template = opts.pages_url_template.as_deref().unwrap_or(provider.default_pages_url_template());
+    // a custom domain serves the site at its root, so the paths only need the subdirectory, if any
+    if let Some(cname) = &opts.cname {
+        let subdir = subdir.unwrap_or_default();
+        let pages_url = match subdir.is_empty() {
+            true => format!("https://{}/", cname),
+            false => format!("https://{}/{}/", cname, subdir),
+        };
+        return (subdir.to_string(), pages_url)
+    }
+    // sites deployed into a subdirectory of the branch are served under that subdirectory as well, 
+    // while user pages repos are served at the root, so a site mounted into one is served under the mount alone
+    match (subdir, opts.mount.is_some()) {
+        (Some(mount), true) => (mount.to_string(), provider.pages_url_at(template, remote, mount)),
+        (Some(subdir), false) => (format!("{}/{}", provider.site_path(remote), subdir), format!("{}{}/", provider.pages_url(template, remote), subdir)),
+        (None, _) => (provider.site_path(remote).to_string(), provider.pages_url(template, remote)),
+    }
+}
+
+/// Rewrites and checks the site in `dist` to be served under `site_path`, then adds the extra files to deploy along with it. 
+/// With `opts.dry_run`, only index.html is rewritten in memory and checked.
+fn prepare_site(cwd: &Path, dist: &Path, site_path: &str, opts: &Options) -> Result<(), Error>{
+    warn_unusual_dist_contents(dist);
+    // sites built with the right public-url only need to be pushed, and sites served at the root of a custom domain don't need any prefix
+    let index_html = match opts.no_index_rewrite || site_path.is_empty() {
+        true => None,
+        false => Some(update_indexhtml(dist, site_path, opts)?),
+    };
+
+    if opts.verify_links {
+        let index_html = match index_html {
+            Some(index_html) => index_html,
+            None => read_to_string(dist.join("index.html"))?,
+        };
+        html::verify_links(&index_html, dist, site_path)?;
+    }
+
+    if opts.dry_run {
+        return Ok(())
+    }
+
+    if opts.recursive_html && !site_path.is_empty() {
+        update_nested_html(dist, site_path, opts)?;
+    }
+
+    if let Some(cname) = &opts.cname {
+        write(dist.join("CNAME"), cname)?;
+    }
+
+    if opts.nojekyll {
+        write_nojekyll(dist)?;
+    }
+
+    if opts.branch_notice {
+        write_branch_notice(dist, opts.branch())?;
+    }
+
+    copy_lfs_attributes(cwd, dist)?;
+
+    if opts.keep_empty_dirs {
+        stage::keep_empty_dirs(dist)?;
+    }
+
+    // written last so that the hashes match the deployed files
+    if opts.manifest {
+        stage::write_manifest(dist, opts)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that `dist` has an index.html to rewrite, since a missing one usually means the site wasn't built yet
+fn check_index_exists(dist: &Path) -> Result<(), Error>{
+    match dist.join("index.html").is_file() {
+        true => Ok(()),
+        false => Err(Error::new(ErrorKind::NotFound, format!("No index.html in {}; did you run `trunk build`?", dist.display()))),
+    }
+}
+
+/// Checks that `dist` isn't the root of the source repo at `cwd`, or one of its parents. 
+/// Deploying it would run `git init` in the source repo and delete its `.git` afterwards, so this can't be skipped with --force.
+fn check_dist_outside_repo(cwd: &Path, dist: &Path) -> Result<(), Error>{
+    let (Some(repo_root), Ok(dist)) = (find_repo_root(cwd), dist.canonicalize()) else {
+        return Ok(())
+    };
+    if repo_root.canonicalize().is_ok_and(|repo_root| repo_root.starts_with(&dist)) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} contains the source repo, and deploying it would destroy the repo's .git. \
+            Build the site into a separate output directory, such as dist", dist.display())))
+    }
+    Ok(())
+}
+
+/// Checks that the source repo at `cwd` isn't checked out on the branch being deployed to, 
+/// since deploying the built site over the branch the site is built from would be a mistake. 
+/// Source directories that aren't git repos are not checked.
+fn check_not_on_branch(cwd: &Path, opts: &Options) -> Result<(), Error>{
+    let Ok(output) = output_step(Command::new(opts.git_bin())
+        .current_dir(cwd)
+        .args(["symbolic-ref","--short","-q","HEAD"])) else {
+        return Ok(())
+    };
+    let current_branch = String::from_utf8_lossy(&output.stdout);
+    if output.status.success() && current_branch.trim() == opts.branch() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("The source repo is checked out on '{}', the branch being deployed to. \
+            Switch to the branch the site is built from, or pass --force to deploy anyway", opts.branch())))
+    }
+    Ok(())
+}
+
+/// Creates an empty `.nojekyll` file in `dir`, which stops Pages from processing the site with Jekyll 
+/// (which would drop files and directories starting with `_`)
+pub fn write_nojekyll(dir: &Path) -> Result<(), Error>{
+    if !dir.is_dir() {
+        return Err(Error::new(ErrorKind::NotFound, format!("{} is not a directory", dir.display())))
+    }
+    write(dir.join(".nojekyll"), "")
+}
+
+/// Appends a markdown table summarizing `outcome` to the file in `GITHUB_STEP_SUMMARY`, which GitHub Actions shows on the page of the job. 
+/// Does nothing outside of GitHub Actions, where the variable isn't set.
+pub fn write_step_summary(outcome: &DeployOutcome) -> Result<(), Error>{
+    let Some(path) = env::var_os("GITHUB_STEP_SUMMARY").filter(|path| !path.is_empty()) else {
+        return Ok(())
+    };
+    let summary = format!("### trunk-ghpages deploy\n\n\
+        | Repo | Branch | Commit | URL | Files |\n\
+        | --- | --- | --- | --- | --- |\n\
+        | {} | {} | {} | {} | {} |\n\n",
+        outcome.repo_name, outcome.branch, outcome.commit_sha.as_deref().map_or("unchanged".to_string(), |sha| format!("`{}`", sha)),
+        outcome.pages_url, outcome.file_count);
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(redact(&summary).as_bytes())
+}
+
+/// Writes a `README.md` into `dir` telling people browsing the deployed branch that it is generated and shouldn't be edited, 
+/// since manual edits are lost on the next deploy. A `README.md` that is part of the site is kept, with a warning.
+pub fn write_branch_notice(dir: &Path, branch: &str) -> Result<(), Error>{
+    let path = dir.join(BRANCH_NOTICE_FILE);
+    if path.exists() {
+        log::warn(format!("Not writing the branch notice, since dist already has a {}", BRANCH_NOTICE_FILE));
+        return Ok(())
+    }
+    write(path, format!("# {}\n\nThis branch is auto-generated by trunk-ghpages; do not edit. \
+        Any changes made to it directly are replaced by the next deploy, so change the source of the site instead.\n", branch))
+}
+
+/// Copies the Git LFS patterns (lines with `filter=lfs`) of the source repo's `.gitattributes` into 
+/// the `.gitattributes` of `dist`, so that large files in the deploy are committed through LFS as well. 
+/// Patterns that are already present in `dist` are not copied again.
+pub fn copy_lfs_attributes(cwd: &Path, dist: &Path) -> Result<(), Error>{
+    let Ok(source_attributes) = read_to_string(cwd.join(".gitattributes")) else {
+        return Ok(())
+    };
+    let dist_attributes = read_to_string(dist.join(".gitattributes")).unwrap_or_default();
+
+    let missing: Vec<&str> = source_attributes.lines()
+        .filter(|line| line.contains("filter=lfs") && !dist_attributes.lines().any(|existing| existing == *line))
+        .collect();
+    if missing.is_empty() {
+        return Ok(())
+    }
+
+    let mut attributes = dist_attributes;
+    if !attributes.is_empty() && !attributes.ends_with('\n') {
+        attributes.push('\n');
+    }
+    attributes.push_str(&missing.join("\n"));
+    attributes.push('\n');
+    write(dist.join(".gitattributes"), attributes)
+}
+
+/// Name of the file written by --branch-notice
+pub const BRANCH_NOTICE_FILE: &str = "README.md";
+
+/// Environment variable overriding the directory temporary directories are created in, like --temp-dir
+pub const TEMP_DIR_ENV: &str = "TRUNK_GHPAGES_TMP";
+
+/// Creates a new, empty directory in the directory given with `--temp-dir` or `TRUNK_GHPAGES_TMP`, 
+/// or else in the system's temp directory
+pub fn create_temp_dir(opts: &Options) -> Result<PathBuf, Error>{
+    let parent = match opts.temp_dir.clone().or(env::var(TEMP_DIR_ENV).ok()) {
+        Some(parent) => {
+            let parent = PathBuf::from(parent);
+            if !parent.is_dir() {
+                return Err(Error::new(ErrorKind::NotFound, format!("The temp directory {} does not exist", parent.display())))
+            }
+            parent
+        }
+        None => env::temp_dir(),
+    };
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos());
+    let dir = parent.join(format!("trunk-ghpages-{}-{}", process::id(), nanos));
+    create_dir(&dir).map_err(|e| match e.kind() {
+        ErrorKind::PermissionDenied => Error::new(ErrorKind::PermissionDenied, 
+            format!("The temp directory {} is not writable. Pass a different one with --temp-dir", parent.display())),
+        _ => e
+    })?;
+    Ok(dir)
+}
+
+/// Builds the project in `cwd` by running `trunk build --release`, which respects the project's Trunk.toml. 
+/// The output of trunk goes to stderr in JSON mode.
+/// 
+/// # Example:
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, *};
+/// # let opts = Options::default();
+/// let cwd = std::env::current_dir()?;
+/// trunk_build(&cwd, &opts)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn trunk_build(cwd: &Path, opts: &Options) -> Result<(), Error>{
+    let mut cmd = Command::new(opts.trunk_bin());
+    // in JSON mode, stdout is only for the JSON output
+    if opts.format == OutputFormat::Json {
+        cmd.stdout(stderr());
+    }
+    let status = cmd
+        .current_dir(cwd)
+        .args(["build","--release"])
+        .status()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::new(ErrorKind::NotFound, 
+                format!("Could not run '{}'. Install trunk with `cargo install trunk`, or pass its location with --trunk-bin", opts.trunk_bin())),
+            _ => e
+        });
+    ensure_success(status)?;
+    Ok(())
+}
+
+/// Extracts a `.zip`, `.tar.gz`, `.tgz` or `.tar` archive of the built site into `dir` using `unzip` or `tar`. 
+/// Windows doesn't ship `unzip`, so zips are extracted with the `tar` it has (bsdtar, since Windows 10), which reads them too.
+/// 
+/// Returns the directory containing the site, which is the single top-level directory of the archive if it 
+/// has one (such as an archived `dist` folder), or `dir` itself otherwise.
+fn extract_archive(archive: &Path, dir: &Path) -> Result<PathBuf, Error>{
+    let name = archive.to_string_lossy().to_ascii_lowercase();
+    let (program, args): (&str, [&OsStr; 4]) = if name.ends_with(".zip") && !cfg!(windows) {
+        ("unzip", ["-q".as_ref(), archive.as_os_str(), "-d".as_ref(), dir.as_os_str()])
+    } else if [".zip", ".tar.gz", ".tgz", ".tar"].iter().any(|extension| name.ends_with(extension)) {
+        ("tar", ["-xf".as_ref(), archive.as_os_str(), "-C".as_ref(), dir.as_os_str()])
+    } else {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Unsupported archive {}, expected a .zip, .tar.gz, .tgz or .tar file", archive.display())))
+    };
+    if !archive.is_file() {
+        return Err(Error::new(ErrorKind::NotFound, format!("Archive {} does not exist", archive.display())))
+    }
+
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::new(ErrorKind::NotFound, format!("Could not run '{}', which is needed to extract {}. Install it, or extract the archive yourself and deploy the directory with --dist", program, archive.display())),
+            _ => e
+        });
+    ensure_success(status)?;
+
+    let entries = dir.read_dir()?.collect::<Result<Vec<_>, Error>>()?;
+    match entries.as_slice() {
+        [entry] if entry.path().is_dir() => Ok(entry.path()),
+        _ => Ok(dir.to_path_buf()),
+    }
+}
+
+/// Recursively collects the paths of all files within `dir`, skipping any `.git` directory
+pub fn walk_files(dir: &Path) -> Result<Vec<PathBuf>, Error>{
+    let mut files = Vec::new();
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|name| name != ".git") {
+                files.append(&mut walk_files(&path)?);
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Warns about contents of dist that usually mean something went wrong, such as a repo left behind by a crashed deploy, 
+/// an empty index.html, or a node_modules folder that would bloat the deploy
+fn warn_unusual_dist_contents(dist: &Path){
+    if dist.join(".git").is_dir() {
+        log::warn(format!("{} already contains a .git directory, probably left behind by an earlier deploy that didn't finish. \
+            Remove it with --repair", dist.display()));
+    }
+    if metadata(dist.join("index.html")).is_ok_and(|metadata| metadata.len() == 0) {
+        log::warn(format!("{} is empty, so the deployed site will be blank", dist.join("index.html").display()));
+    }
+    if let Some(node_modules) = walk_files(dist).unwrap_or_default().iter()
+        .find_map(|path| path.strip_prefix(dist).ok()?.ancestors().find(|dir| dir.file_name().is_some_and(|name| name == "node_modules"))) {
+        log::warn(format!("{} will be deployed, which is usually a mistake in the build setup", dist.join(node_modules).display()));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+
+    use super::*;
+
+    const INDEX_HTML: &str = "<script src=\"/app.js\"></script><img src=\"/assets/a.png\">";
+
+    /// Sets the identity the temporary repos of the deploys commit with
+    fn set_identity() {
+        for (var, value) in [("GIT_AUTHOR_NAME", "Deployer"), ("GIT_AUTHOR_EMAIL", "deployer@example.com"),
+            ("GIT_COMMITTER_NAME", "Deployer"), ("GIT_COMMITTER_EMAIL", "deployer@example.com")] {
+            env::set_var(var, value);
+        }
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git").current_dir(dir).args(args).output().unwrap();
+        assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Creates a project named `name` in a fresh temp directory, with a small site in dist 
+    /// and an empty bare repo named `repo` to deploy it to. Returns the project directory and the URL of the repo.
+    fn project(name: &str, repo: &str) -> (PathBuf, String) {
+        let cwd = env::temp_dir().join(format!("trunk-ghpages-{}-{}", name, process::id()));
+        let _ = remove_dir_all(&cwd);
+        create_dir_all(cwd.join("dist/assets")).unwrap();
+        write(cwd.join("dist/index.html"), INDEX_HTML).unwrap();
+        write(cwd.join("dist/app.js"), "").unwrap();
+        write(cwd.join("dist/assets/a.png"), "").unwrap();
+        let remote = bare_remote(&cwd, repo);
+        (cwd, remote)
+    }
+
+    /// Creates an empty bare repo named `repo` in the project at `cwd`, returning its URL
+    fn bare_remote(cwd: &Path, repo: &str) -> String {
+        let remote = cwd.join(format!("remotes/{}.git", repo)).to_string_lossy().into_owned();
+        git(cwd, &["init","-q","--bare",&remote]);
+        remote
+    }
+
+    /// Makes the project at `cwd` a git repo with one commit, returning the sha of HEAD
+    fn source_repo(cwd: &Path) -> String {
+        git(cwd, &["init","-q","-b","main"]);
+        git(cwd, &["commit","-q","--allow-empty","-m","Initial commit"]);
+        git(cwd, &["rev-parse","HEAD"])
+    }
+
+    /// The options of a non-interactive deploy to `remote`
+    fn options(remote: &str) -> Options {
+        Options { repo: Some(remote.to_string()), yes: true, force: true, quiet: true, ..Options::default() }
+    }
+
+    #[test]
+    fn failed_push_restores_index_html() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, _) = project("restore", "MyRepo");
+        let dist = cwd.join("dist");
+        let remote = cwd.join("missing/MyRepo.git").to_string_lossy().into_owned();
+        let res = deploy_dist(&cwd, &dist, &remote, &options(&remote));
+        let index = read_to_string(dist.join("index.html")).unwrap();
+        let leftover_git = dist.join(".git").exists();
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(matches!(res, Err(DeployError::Push(_))), "{:?}", res);
+        assert_eq!(index, INDEX_HTML);
+        assert!(!leftover_git);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failed_check_after_the_push_keeps_the_deployed_index_html() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("strict-after-push", "MyRepo");
+        // without git-lfs, the LFS patterns in dist give a warning while pushing
+        let git_bin = cwd.join("git-without-lfs");
+        write(&git_bin, "#!/bin/sh\n[ \"$1\" = lfs ] && exit 1\nexec git \"$@\"\n").unwrap();
+        std::fs::set_permissions(&git_bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        write(cwd.join("dist/.gitattributes"), "*.png filter=lfs diff=lfs merge=lfs -text\n").unwrap();
+        let opts = Options { strict: true, git_bin: Some(git_bin.to_string_lossy().into_owned()), ..options(&remote) };
+
+        let res = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let index = read_to_string(cwd.join("dist/index.html")).unwrap();
+        let deployed = git(Path::new(&remote), &["show","gh-pages:index.html"]);
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(matches!(res, Err(DeployError::Strict(_))), "{:?}", res);
+        assert!(index.contains("\"/MyRepo/app.js\""), "{}", index);
+        assert_eq!(index, deployed);
+    }
+
+    #[test]
+    fn branch_notice_is_written_on_every_deploy_without_leaving_it_in_dist() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("notice-twice", "MyRepo");
+        let opts = Options { branch_notice: true, strict: true, ..options(&remote) };
+        let first = deploy(DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        let left_in_dist = cwd.join("dist").join(BRANCH_NOTICE_FILE).exists();
+        let second = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let notice = git(Path::new(&remote), &["show",&format!("gh-pages:{}", BRANCH_NOTICE_FILE)]);
+        let files = git(Path::new(&remote), &["ls-tree","-r","--name-only","gh-pages"]);
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(first.is_ok(), "{:?}", first);
+        assert!(!left_in_dist);
+        assert!(second.is_ok(), "{:?}", second);
+        assert!(notice.contains("do not edit"), "{}", notice);
+        assert_eq!(files.lines().collect::<Vec<_>>(), [BRANCH_NOTICE_FILE, "app.js", "assets/a.png", "index.html"]);
+    }
+
+    #[test]
+    fn strict_only_checks_the_warnings_of_its_own_deploy() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("strict-twice", "MyRepo");
+        let opts = Options { strict: true, ..options(&remote) };
+        let warnings = log::warning_count();
+
+        // the first deploy warns about node_modules, and fails because of --strict
+        create_dir_all(cwd.join("dist/node_modules/pkg")).unwrap();
+        write(cwd.join("dist/node_modules/pkg/index.js"), "").unwrap();
+        let first = deploy(DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        remove_dir_all(cwd.join("dist/node_modules")).unwrap();
+        let second = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let warnings_after = log::warning_count();
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(matches!(&first, Err(DeployError::Site(e)) if e.to_string().contains("--strict")), "{:?}", first);
+        assert!(second.as_ref().is_ok_and(|outcome| outcome.changed), "{:?}", second);
+        assert_eq!(warnings_after, warnings);
+    }
+
+    #[test]
+    fn every_outcome_only_has_its_own_steps() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("steps-twice", "MyRepo");
+        let steps = log::steps();
+        let first = deploy(DeployConfig { project_dir: cwd.clone(), options: options(&remote) }).unwrap();
+        write(cwd.join("dist/app.js"), "changed();").unwrap();
+        let second = deploy(DeployConfig { project_dir: cwd.clone(), options: options(&remote) }).unwrap();
+        let checks = preflight(&DeployConfig { project_dir: cwd.clone(), options: options(&remote) });
+        let steps_after = log::steps();
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(first.steps.iter().any(|step| step.command.contains(" push ")), "{:?}", first.steps);
+        assert_eq!(second.steps.len(), first.steps.len(), "{:?}", second.steps);
+        assert_eq!(second.timings.iter().map(|(phase, _)| *phase).collect::<Vec<_>>(), first.timings.iter().map(|(phase, _)| *phase).collect::<Vec<_>>());
+        assert!(checks.iter().all(|check| check.result.is_ok()));
+        assert_eq!(steps_after, steps);
+    }
+
+    #[test]
+    fn commit_message_fills_in_the_build_number() {
+        let opts = Options { message: Some("Deploy build {build} to {branch}".to_string()), build_number: Some(42), ..Options::default() };
+        assert_eq!(commit_message(&opts).unwrap(), "Deploy build 42 to gh-pages");
+        assert_eq!(commit_message(&Options::default()).unwrap(), "Update gh-pages");
+    }
+
+    #[test]
+    fn commit_message_without_a_build_number() {
+        let opts = Options { message: Some("Deploy {branch}".to_string()), ..Options::default() };
+        assert_eq!(commit_message(&opts).unwrap(), "Deploy gh-pages");
+
+        let opts = Options { message: Some("Deploy build {build}".to_string()), ..Options::default() };
+        let e = commit_message(&opts).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("--build-number"), "{}", e);
+    }
+
+    #[test]
+    fn step_summary_is_appended_to_the_file() {
+        let _lock = log::test_lock();
+        let path = env::temp_dir().join(format!("trunk-ghpages-summary-{}.md", process::id()));
+        write(&path, "## Build\n\n").unwrap();
+        let outcome = DeployOutcome {
+            repo_name: "MyRepo".to_string(),
+            branch: "gh-pages".to_string(),
+            commit_sha: Some("abc123".to_string()),
+            pages_url: "https://user.github.io/MyRepo/".to_string(),
+            changed: true,
+            skipped: false,
+            file_count: 3,
+            steps: Vec::new(),
+            timings: Vec::new(),
+        };
+        env::set_var("GITHUB_STEP_SUMMARY", &path);
+        let first = write_step_summary(&outcome);
+        let second = write_step_summary(&DeployOutcome { commit_sha: None, ..outcome });
+        env::remove_var("GITHUB_STEP_SUMMARY");
+        let summary = read_to_string(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert!(first.is_ok() && second.is_ok());
+        let table = "### trunk-ghpages deploy\n\n\
+            | Repo | Branch | Commit | URL | Files |\n\
+            | --- | --- | --- | --- | --- |\n";
+        assert_eq!(summary, format!("## Build\n\n\
+            {table}| MyRepo | gh-pages | `abc123` | https://user.github.io/MyRepo/ | 3 |\n\n\
+            {table}| MyRepo | gh-pages | unchanged | https://user.github.io/MyRepo/ | 3 |\n\n"));
+    }
+
+    #[test]
+    fn since_last_deploy_skips_an_unchanged_source() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("since-unchanged", "MyRepo");
+        source_repo(&cwd);
+        let opts = Options { since_last_deploy: true, ..options(&remote) };
+        let first = deploy(DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        let tip = git(Path::new(&remote), &["rev-parse","gh-pages"]);
+        let second = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let tip_after = git(Path::new(&remote), &["rev-parse","gh-pages"]);
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(!first.as_ref().unwrap().skipped, "{:?}", first);
+        let second = second.unwrap();
+        assert!(second.skipped);
+        assert_eq!(second.commit_sha, None);
+        assert_eq!(tip_after, tip);
+    }
+
+    #[test]
+    fn since_last_deploy_deploys_a_changed_source() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("since-changed", "MyRepo");
+        source_repo(&cwd);
+        let opts = Options { since_last_deploy: true, ..options(&remote) };
+        let first = deploy(DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        git(&cwd, &["commit","-q","--allow-empty","-m","Change the site"]);
+        let source = git(&cwd, &["rev-parse","HEAD"]);
+        let second = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let message = git(Path::new(&remote), &["log","-1","--format=%B","gh-pages"]);
+        let commits = git(Path::new(&remote), &["rev-list","--count","gh-pages"]);
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(first.is_ok(), "{:?}", first);
+        let second = second.unwrap();
+        assert!(!second.skipped);
+        assert!(second.commit_sha.is_some());
+        assert!(message.contains(&format!("Source-Commit: {}", source)), "{}", message);
+        assert_eq!(commits, "1");
+    }
+
+    #[test]
+    fn source_trailers_end_the_commit_message() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("trailers", "MyRepo");
+        let source = source_repo(&cwd);
+        let opts = Options { source_trailer: true, ..options(&remote) };
+        let res = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let message = git(Path::new(&remote), &["log","-1","--format=%B","gh-pages"]);
+        let trailers = git(Path::new(&remote), &["log","-1","--format=%(trailers:only,unfold)","gh-pages"]);
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(message, format!("Update gh-pages\n\nSource-Commit: {}\nSource-Ref: refs/heads/main", source));
+        // git itself parses them as trailers, like --since-last-deploy does
+        assert_eq!(trailers, format!("Source-Commit: {}\nSource-Ref: refs/heads/main", source));
+    }
+
+    #[test]
+    fn every_target_gets_its_own_prefix() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, first) = project("targets", "FirstSite");
+        let second = bare_remote(&cwd, "SecondSite");
+        let targets = vec![(first.clone(), "gh-pages".to_string()), (second.clone(), "pages".to_string())];
+        let results = deploy_targets(DeployConfig { project_dir: cwd.clone(), options: Options { targets, ..options(&first) } });
+        let deployed = [(&first, "gh-pages"), (&second, "pages")].map(|(remote, branch)| git(Path::new(remote), &["show",&format!("{}:index.html", branch)]));
+        let index = read_to_string(cwd.join("dist/index.html")).unwrap();
+        remove_dir_all(&cwd).unwrap();
+
+        assert_eq!(results.len(), 2);
+        for (target, res) in &results {
+            assert!(res.is_ok(), "{}: {:?}", target, res);
+        }
+        assert_eq!(results[0].1.as_ref().unwrap().repo_name, "FirstSite");
+        assert_eq!(results[1].1.as_ref().unwrap().repo_name, "SecondSite");
+        assert_eq!(deployed[0], "<script src=\"/FirstSite/app.js\"></script><img src=\"/FirstSite/assets/a.png\">");
+        assert_eq!(deployed[1], "<script src=\"/SecondSite/app.js\"></script><img src=\"/SecondSite/assets/a.png\">");
+        // the targets deploy copies of dist, so dist itself is left as it is
+        assert_eq!(index, INDEX_HTML);
+    }
+
+    #[test]
+    fn cname_from_the_config_file_serves_the_site_at_the_root() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("cname", "MyRepo");
+        write(cwd.join(config::CONFIG_FILE), "cname = \"example.com\"\n").unwrap();
+        let mut opts = options(&remote);
+        opts.apply(&ConfigFile::load(&cwd).unwrap().settings).unwrap();
+        let outcome = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let cname = git(Path::new(&remote), &["show","gh-pages:CNAME"]);
+        let deployed = git(Path::new(&remote), &["show","gh-pages:index.html"]);
+        remove_dir_all(&cwd).unwrap();
+
+        let outcome = outcome.unwrap();
+        assert_eq!(cname, "example.com");
+        assert_eq!(deployed, INDEX_HTML);
+        assert_eq!(outcome.repo_name, "");
+        assert_eq!(outcome.pages_url, "https://example.com/");
+    }
+
+    #[test]
+    fn site_location_of_a_mounted_site() {
+        let user_site = RemoteUrl::parse("https://github.com/FradulentUser/fradulentuser.github.io.git");
+        let opts = Options { mount: Some("my-app".to_string()), ..Options::default() };
+        assert_eq!(site_location(&user_site, Some("my-app"), &opts), ("my-app".to_string(), "https://fradulentuser.github.io/my-app/".to_string()));
+        for mount in ["my-app/", "/my-app/"] {
+            let opts = Options { mount: Some(mount.to_string()), ..Options::default() };
+            assert_eq!(site_location(&user_site, Some(mount), &opts), ("my-app".to_string(), "https://fradulentuser.github.io/my-app/".to_string()));
+        }
+    }
+
+    #[test]
+    fn site_location_without_a_mount() {
+        let remote = RemoteUrl::parse("https://github.com/FradulentUser/MyRepo.git");
+        assert_eq!(site_location(&remote, None, &Options::default()), ("MyRepo".to_string(), "https://fradulentuser.github.io/MyRepo/".to_string()));
+        let opts = Options { target_subdir: Some("docs/".to_string()), ..Options::default() };
+        assert_eq!(site_location(&remote, Some("docs/"), &opts), ("MyRepo/docs".to_string(), "https://fradulentuser.github.io/MyRepo/docs/".to_string()));
+    }
+
+    #[test]
+    fn pages_branch_readme_is_only_seeded_on_the_first_deploy() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("seed-readme", "MyRepo");
+        let opts = Options { pages_branch_readme: true, strict: true, ..options(&remote) };
+        let first = deploy(DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        let seeded = git(Path::new(&remote), &["show",&format!("gh-pages:{}", BRANCH_NOTICE_FILE)]);
+
+        // a maintainer edits the README on the branch
+        let clone = cwd.join("clone");
+        git(&cwd, &["clone","-q","-b","gh-pages",&remote,&clone.to_string_lossy()]);
+        write(clone.join(BRANCH_NOTICE_FILE), "# Edited\n").unwrap();
+        git(&clone, &["commit","-q","-am","Edit the README"]);
+        git(&clone, &["push","-q","origin","gh-pages"]);
+
+        write(cwd.join("dist/app.js"), "changed();").unwrap();
+        let second = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let kept = git(Path::new(&remote), &["show",&format!("gh-pages:{}", BRANCH_NOTICE_FILE)]);
+        let app = git(Path::new(&remote), &["show","gh-pages:app.js"]);
+        let left_in_dist = cwd.join("dist").join(BRANCH_NOTICE_FILE).exists();
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(first.is_ok(), "{:?}", first);
+        assert!(seeded.contains("auto-generated by trunk-ghpages"), "{}", seeded);
+        assert!(second.unwrap().changed);
+        assert_eq!(kept, "# Edited");
+        assert_eq!(app, "changed();");
+        assert!(!left_in_dist);
+    }
+
+    #[test]
+    fn settings_are_applied_in_order_of_precedence() {
+        let dir = env::temp_dir().join(format!("trunk-ghpages-precedence-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        write(dir.join(config::CONFIG_FILE), "branch = \"config-branch\"\ndist = \"config-dist\"\n\
+            cname = \"config.example.com\"\nprovider = \"gitlab\"\n\n\
+            [env.prod]\nbranch = \"prod-branch\"\ndist = \"prod-dist\"\ncname = \"prod.example.com\"\n").unwrap();
+        write(dir.join(config::TRUNK_CONFIG_FILE), "[build]\ntarget = \"index.html\"\n\n\
+            [tool.trunk-ghpages]\nremote = \"https://github.com/trunk/Trunk.git\"\nbranch = \"trunk-branch\"\nprovider = \"github\"\n").unwrap();
+        let env = Settings { branch: Some("env-branch".to_string()), dist: Some("env-dist".to_string()), ..Settings::default() };
+
+        let mut opts = Options::parse(["--env", "prod", "--branch", "cli-branch"].map(String::from)).unwrap();
+        let res = apply_settings(&mut opts, &dir, &env);
+        let config = effective_config(&DeployConfig { project_dir: dir.clone(), options: opts.clone() });
+        let mut without_env = Options::default();
+        apply_settings(&mut without_env, &dir, &Settings::default()).unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        assert!(res.is_ok(), "{:?}", res);
+        // each one comes from the highest source that sets it
+        assert_eq!(opts.branch(), "cli-branch");
+        assert_eq!(opts.dist(), "env-dist");
+        assert_eq!(opts.cname.as_deref(), Some("prod.example.com"));
+        assert_eq!(opts.provider().to_string(), "gitlab");
+        assert_eq!(opts.repo.as_deref(), Some("https://github.com/trunk/Trunk.git"));
+        let config = config.unwrap();
+        assert!(config.contains("\"branch\": \"cli-branch\"") && config.contains("\"remote\": \"https://github.com/trunk/Trunk.git\""), "{}", config);
+
+        assert_eq!(without_env.branch(), "config-branch");
+        assert_eq!(without_env.dist(), "config-dist");
+        assert_eq!(without_env.cname.as_deref(), Some("config.example.com"));
+    }
+
+    #[test]
+    fn dist_containing_the_source_repo_is_rejected() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("dist-outside-repo", "MyRepo");
+        source_repo(&cwd);
+        let outside = check_dist_outside_repo(&cwd, &cwd.join("dist"));
+        let root = check_dist_outside_repo(&cwd, &cwd);
+        let root_with_dot = check_dist_outside_repo(&cwd, &cwd.join("."));
+        let ancestor = check_dist_outside_repo(&cwd, cwd.parent().unwrap());
+        write(cwd.join("index.html"), INDEX_HTML).unwrap();
+        let deployed = deploy(DeployConfig { project_dir: cwd.clone(), options: Options { dist: Some(".".to_string()), ..options(&remote) } });
+        let head = git(&cwd, &["log","-1","--format=%s"]);
+        remove_dir_all(&cwd).unwrap();
+
+        assert!(outside.is_ok(), "{:?}", outside);
+        for res in [root, root_with_dot, ancestor] {
+            let e = res.unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput);
+            assert!(e.to_string().contains("contains the source repo"), "{}", e);
+        }
+        assert!(matches!(&deployed, Err(DeployError::Config(e)) if e.to_string().contains("contains the source repo")), "{:?}", deployed);
+        // the source repo is left intact
+        assert_eq!(head, "Initial commit");
+    }
+
+    /// A remote whose port refuses connections, so accessing it fails right away with git's error
+    const UNREACHABLE_REMOTE: &str = "http://127.0.0.1:9/FradulentUser/MyRepo.git";
+
+    #[test]
+    fn credential_helper_token_is_redacted_from_errors_and_steps() {
+        let _lock = log::test_lock();
+        let token = "ghp_helperSecret128";
+        let opts = Options { token: Some(token.to_string()), credential_helper: true, ..Options::default() };
+        let push_url = push_url(UNREACHABLE_REMOTE, &opts).unwrap();
+        let cmd = push::remote_git(&env::temp_dir(), &opts);
+        let helper_token = cmd.get_envs().find(|(var, _)| *var == "TRUNK_GHPAGES_TOKEN").and_then(|(_, value)| value.map(OsStr::to_os_string));
+        let args: Vec<String> = cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        let res = check_remote_access(&env::temp_dir(), &push_url, &opts);
+        let steps = log::steps();
+
+        // the helper gets the token from its environment, so it's neither in the URL nor in the arguments
+        assert_eq!(push_url, UNREACHABLE_REMOTE);
+        assert_eq!(helper_token.as_deref(), Some(OsStr::new(token)));
+        assert!(args.iter().all(|arg| !arg.contains(token)), "{:?}", args);
+        let message = redact(&res.unwrap_err().to_string());
+        assert!(message.starts_with("Could not access") && !message.contains(token), "{}", message);
+        assert!(steps.iter().any(|step| step.command.contains("ls-remote")), "{:?}", steps);
+        assert!(steps.iter().all(|step| !step.command.contains(token)), "{:?}", steps);
+        // anything else printed that mentions the token is masked as well
+        assert_eq!(redact(&format!("The remote rejected the token {}", token)), "The remote rejected the token ***");
+    }
+
+    #[test]
+    fn token_in_the_remote_url_env_is_redacted_from_errors_and_steps() {
+        let _lock = log::test_lock();
+        let token = "ghp_envSecret152";
+        let var = "TRUNK_GHPAGES_TEST_REMOTE_URL";
+        env::set_var(var, UNREACHABLE_REMOTE.replace("://", &format!("://{}@", token)));
+        let opts = Options { remote_url_env: Some(var.to_string()), ..Options::default() };
+        let cwd = env::temp_dir();
+        let remote = resolve_remote(&cwd, &opts);
+        let config = effective_config(&DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        let res = check_remote_access(&cwd, remote.as_deref().unwrap_or_default(), &opts);
+        let steps = log::steps();
+        let auth = check_auth(DeployConfig { project_dir: cwd, options: opts });
+        env::remove_var(var);
+
+        // the URL is pushed to as it is, with the token
+        assert!(remote.unwrap().contains(token));
+        let config = config.unwrap();
+        assert!(config.contains("\"remote\": \"http://***@127.0.0.1:9/FradulentUser/MyRepo.git\"") && !config.contains(token), "{}", config);
+        for message in [res.unwrap_err().to_string(), auth.unwrap_err().to_string()] {
+            assert!(message.contains("http://***@127.0.0.1:9/FradulentUser/MyRepo.git") && !message.contains(token), "{}", message);
+        }
+        assert!(steps.iter().any(|step| step.command.contains("ls-remote --heads http://***@127.0.0.1:9/")), "{:?}", steps);
+        assert!(steps.iter().all(|step| !step.command.contains(token)), "{:?}", steps);
+    }
+
+    #[test]
+    fn leftover_git_and_empty_index_html_are_warned_about() {
+        let _lock = log::test_lock();
+        set_identity();
+        let (cwd, remote) = project("unusual-dist", "MyRepo");
+        let dist = cwd.join("dist");
+        let opts = Options { strict: true, ..options(&remote) };
+        // --strict fails these deploys before anything is pushed
+        write(dist.join("index.html"), "").unwrap();
+        let empty_index = deploy(DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        write(dist.join("index.html"), INDEX_HTML).unwrap();
+        create_dir_all(dist.join(".git")).unwrap();
+        let leftover_git = deploy(DeployConfig { project_dir: cwd.clone(), options: opts });
+        let branches = git(Path::new(&remote), &["branch","--list"]);
+
+        warn_unusual_dist_contents(&dist);
+        let leftover_git_warnings = log::warning_count();
+        remove_dir_all(dist.join(".git")).unwrap();
+        warn_unusual_dist_contents(&dist);
+        let usual_warnings = log::warning_count() - leftover_git_warnings;
+        write(dist.join("index.html"), "").unwrap();
+        warn_unusual_dist_contents(&dist);
+        let empty_index_warnings = log::warning_count() - leftover_git_warnings;
+        remove_dir_all(&cwd).unwrap();
+
+        for res in [empty_index, leftover_git] {
+            assert!(matches!(&res, Err(DeployError::Site(e)) if e.to_string().contains("--strict")), "{:?}", res);
+        }
+        assert_eq!(branches, "");
+        assert_eq!(leftover_git_warnings, 1);
+        assert_eq!(usual_warnings, 0);
+        assert_eq!(empty_index_warnings, 1);
+    }
+
+    #[test]
+    fn branch_notice_says_the_branch_is_generated() {
+        let _lock = log::test_lock();
+        let dir = env::temp_dir().join(format!("trunk-ghpages-notice-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        let written = write_branch_notice(&dir, "gh-pages");
+        let notice = read_to_string(dir.join(BRANCH_NOTICE_FILE)).unwrap();
+        write(dir.join(BRANCH_NOTICE_FILE), "# My site\n").unwrap();
+        let kept = write_branch_notice(&dir, "gh-pages");
+        let readme = read_to_string(dir.join(BRANCH_NOTICE_FILE)).unwrap();
+        remove_dir_all(&dir).unwrap();
+
+        assert!(written.is_ok(), "{:?}", written);
+        assert_eq!(notice, "# gh-pages\n\nThis branch is auto-generated by trunk-ghpages; do not edit. \
+            Any changes made to it directly are replaced by the next deploy, so change the source of the site instead.\n");
+        // a README.md of the site is deployed as it is
+        assert!(kept.is_ok(), "{:?}", kept);
+        assert_eq!(readme, "# My site\n");
+        assert_eq!(log::warning_count(), 1);
+    }
+
+    #[test]
+    fn missing_index_html_is_reported_before_running_git() {
+        let _lock = log::test_lock();
+        let (cwd, remote) = project("missing-index", "MyRepo");
+        remove_file(cwd.join("dist/index.html")).unwrap();
+        // any git command would fail with a different error
+        let opts = Options { git_bin: Some(cwd.join("no-git").to_string_lossy().into_owned()), since_last_deploy: true, ..options(&remote) };
+        let res = deploy(DeployConfig { project_dir: cwd.clone(), options: opts.clone() });
+        let no_rewrite = deploy(DeployConfig { project_dir: cwd.clone(), options: Options { no_index_rewrite: true, ..opts } });
+        remove_dir_all(&cwd).unwrap();
+
+        let message = match res {
+            Err(DeployError::Site(e)) => e.to_string(),
+            res => panic!("{:?}", res),
+        };
+        assert_eq!(message, format!("No index.html in {}; did you run `trunk build`?", cwd.join("dist").display()));
+        // without rewriting index.html, it isn't needed
+        assert!(matches!(no_rewrite, Err(DeployError::Config(_))), "{:?}", no_rewrite);
+    }
+}
@@ -1,123 +1,122 @@
-use std::{env::current_dir, fs::{self, remove_dir_all, File}, io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Seek, SeekFrom, Write}, path::Path, process::{Command, ExitStatus}};
+use std::{env::{self, current_dir}, io::Error, process::ExitCode, time::Instant};
 
-fn main() -> Result<(), Error>{
-    let cwd = current_dir()?;
+use trunk_ghpages::{apply_settings, check_auth, cli::{Options, OutputFormat, Subcommand, USAGE}, config::Settings, deploy, deploy_targets, effective_config, find_project_dir, log, preflight, prune, repair, stage, write_nojekyll, DeployConfig};
 
-    let remote_origin = get_remote_origin(&cwd)?;
+fn main() -> ExitCode{
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", log::redact(&e.to_string()));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Error>{
+    let mut opts = Options::parse(env::args().skip(1))?;
+    if opts.help {
+        println!("{}", USAGE);
+        return Ok(())
+    }
 
-    update_indexhtml(&cwd.join("dist"), &remote_origin)?;
+    log::set_quiet(opts.quiet);
+    log::set_verbose(opts.verbose);
 
-    push_dir_to_branch(&remote_origin, &cwd.join("dist"), "gh-pages")?;
+    let reading = Instant::now();
+    let cwd = find_project_dir(&current_dir()?);
 
-    Ok(())
-}
+    apply_settings(&mut opts, &cwd, &Settings::from_env()?)?;
+    log::add_timing("config", reading.elapsed());
+
+    if let Some(Subcommand::TouchNojekyll { dir }) = &opts.command {
+        return write_nojekyll(&cwd.join(dir.as_deref().unwrap_or(opts.dist())))
+    }
 
-/// Get the remote origin by checking ./.git/config in the current working directory
-/// 
-/// On success, returns Ok(String) where the string represents the URL of the remote origin\
-/// On error, returns Err(std::io::Error)\
-/// See std::io::Error for more info on the error return value.
-/// # Example:
-/// ```
-/// let cwd = std::env::current_dir()?;
-/// let remote_origin = get_remote_origin(&cwd)?;
-/// ```
-fn get_remote_origin(cwd: &Path) -> Result<String, Error>{
-    // open config file in read mode
-    let config_file = File::options()
-        .read(true)
-        .write(false)
-        .open(cwd.join(".git/config"))?;
-    
-    // create a buffer to iterate through the lines of the config file
-    let mut read_lines = BufReader::new(config_file)
-        .lines().into_iter().map_while(Result::ok);
-
-    // progress the iterator until the line containing [remote "origin"] is found 
-    if read_lines.find(|line| line.contains("[remote \"origin\"]")).is_none(){
-        return Err(Error::new(ErrorKind::NotFound, "Could not find remote origin in .git/config"))
+    if opts.list {
+        print!("{}", stage::list_deployed_files(&cwd.join(opts.dist()), opts.format, &opts)?);
+        return Ok(())
     }
 
-    // get the actual url, or return error if not found
-    if let Some(line) = read_lines.find(|line| line.contains("url") || line.contains('[')){
-        if line.contains("url"){
-            if let Some((_, url)) = line.split_once('='){
-                return Ok(url.trim().to_string())
+    if opts.check {
+        let checks = preflight(&DeployConfig { project_dir: cwd, options: opts });
+        for check in &checks {
+            match &check.result {
+                Ok(found) => println!("pass  {}: {}", check.name, found),
+                Err(e) => println!("FAIL  {}: {}", check.name, log::redact(&e.to_string())),
             }
         }
+        let failed = checks.iter().filter(|check| check.result.is_err()).count();
+        if failed > 0 {
+            return Err(Error::other(format!("{} of {} checks failed", failed, checks.len())))
+        }
+        return Ok(())
     }
-    Err(Error::new(ErrorKind::NotFound, "Could not find remote origin URL in .git/config"))
-}
 
+    if opts.repair {
+        let dist = cwd.join(opts.dist());
+        match repair(DeployConfig { project_dir: cwd, options: opts })? {
+            true => println!("Removed the leftover {}", dist.join(".git").display()),
+            false => println!("{} has no leftover .git directory, nothing to repair", dist.display()),
+        }
+        return Ok(())
+    }
 
-/// Updates index.html to use the correct file paths for gh-pages to work correctly. 
-/// 
-/// # Example
-/// ```
-/// let cwd = std::env::current_dir()?;
-/// update_indexhtml(cwd.join("dist"), "https://github.com/FradulentUser/MyRepo.git")?;
-/// ```
-fn update_indexhtml(dist_path: &Path, remote_origin: &str) -> Result<(), Error>{
-    let repo_name = remote_origin.rsplit_once(".git")
-                                                .unwrap_or((remote_origin,"")).0
-                                                .rsplit_once("/")
-                                                .unwrap_or(("",remote_origin)).1;
-    let dirs: Vec<String> = dist_path.read_dir()?
-                                .map_while(Result::ok)
-                                .filter_map(|dir| Some(dir.path().file_name()?.to_str()?.to_owned()))
-                                .collect();
-    
-    let mut index_html = File::options().read(true).write(true).open(dist_path.join("index.html"))?;
-    
-    // modify index.html to use the correct file paths
-    let read_lines: Vec<String> = BufReader::new(&index_html)
-        .lines().into_iter().map_while(Result::ok)
-        .map(|mut line| {
-            for dir in dirs.iter() {
-                if let Some(idx) = line.find(dir){line.insert_str(idx,&format!("{}/",repo_name))}
-            }
-            line
-            }).collect();
-    
-    index_html.seek(SeekFrom::Start(0))?;
-    index_html.write_all(read_lines.join("\n").as_bytes())?;
+    if opts.print_config {
+        print!("{}", effective_config(&DeployConfig { project_dir: cwd, options: opts })?);
+        return Ok(())
+    }
 
-    Ok(())
-}
+    if opts.check_auth {
+        let remote = check_auth(DeployConfig { project_dir: cwd, options: opts })?;
+        println!("Authentication works, {} can be accessed", remote);
+        return Ok(())
+    }
 
-/// Commits and force pushes the contents of the specified directory to the given branch of the remote origin
-/// 
-/// # Example
-/// ```
-/// let cwd = current_dir()?;
-/// push_dir_to_branch("https://github.com/FradulentUser/MyRepo.git", cwd.join("src"), "gh-pages")
-/// ```
-fn push_dir_to_branch(remote_origin: &str, dir: &Path, branch: &str) -> Result<(), Error>{
-    let cmds_args: [&[&str]; 6] = [&["init"],
-        &["remote","add","origin",remote_origin],
-        &["add","."],
-        &["commit","-am",&format!("Update {}",branch)],
-        &["branch",branch],
-        &["push","-uf","origin",branch]];
-
-    for args in cmds_args{
-        ensure_success(Command::new("git")
-            .current_dir(dir)
-            .args(args)
-            .status())?;
+    if let Some(pattern) = opts.prune.clone() {
+        let pruned = prune(DeployConfig { project_dir: cwd, options: opts }, &pattern)?;
+        if pruned > 0 {
+            println!("Pruned {} branch(es)", pruned);
+        }
+        return Ok(())
     }
 
-    remove_dir_all(dir.join(".git"))
-}
+    let (format, verbose) = (opts.format, opts.verbose);
+    if !opts.targets.is_empty() {
+        let total = opts.targets.len();
+        let results = deploy_targets(DeployConfig { project_dir: cwd, options: opts });
+        let mut outcomes = Vec::new();
+        for (target, result) in &results {
+            match result {
+                Ok(outcome) if format == OutputFormat::Json => outcomes.push(outcome.to_json(verbose).trim_end().to_string()),
+                Ok(outcome) => println!("ok    {} -> {}", target, outcome.pages_url),
+                Err(e) => eprintln!("FAIL  {}: {}", target, log::redact(&e.to_string())),
+            }
+        }
+        if format == OutputFormat::Json {
+            match outcomes.is_empty() {
+                true => println!("[]"),
+                false => println!("[\n{}\n]", outcomes.join(",\n")),
+            }
+        }
+        let deployed = results.iter().filter(|(_, result)| result.is_ok()).count();
+        if deployed < total {
+            return Err(Error::other(format!("Deployed to {} of {} targets", deployed, total)))
+        }
+        return Ok(())
+    }
+
+    let pushes = !opts.dry_run && !opts.offline && !opts.commit_only && opts.eject.is_none();
+    let outcome = deploy(DeployConfig { project_dir: cwd, options: opts })?;
+    if format == OutputFormat::Json {
+        print!("{}", outcome.to_json(verbose));
+    } else {
+        if pushes && !outcome.skipped {
+            println!("Deployed to {}", outcome.pages_url);
+        }
+        if !log::is_quiet() {
+            print!("Timings:\n{}", outcome.timings_table());
+        }
+    }
 
-/// Transforms a Result<ExitStatus, Error> so that Ok(ExitStatus) is only returned if the exit status is a success. 
-/// Otherwise, returns an error.
-/// 
-/// # Example:
-/// ```
-/// let success_status = ensure_success(Command::new("ls").status()?);
-/// ```
-fn ensure_success(res: Result<ExitStatus, Error>) -> Result<ExitStatus, Error>{
-    let res = res?;
-    res.success().then(|| res).ok_or(Error::new(ErrorKind::Other, res.to_string()))
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use std::{cmp::Ordering, fs::{read_to_string, remove_dir_all, write}, io::{Error, ErrorKind}, path::Path, process::Command, time::Instant};
+
+use crate::{cli::Options, create_temp_dir, log, push::{add_all, confirm_force_push, fetch_remote_branch, head_sha, install_lfs, output_step, push_step, remote_git, run_step, seed_branch_readme, set_origin, truncate_history, Commit}, stage};
+
+/// Marks index.html files generated by trunk-ghpages, which may be overwritten without --force
+const GENERATED_MARKER: &str = "<!-- generated by trunk-ghpages -->";
+
+/// Commits the contents of `dist` into `subdir` of the given branch, keeping everything else on the branch 
+/// along with its history, then pushes it to the remote origin. 
+/// 
+/// Unlike push_dir_to_branch, this works in a temporary clone of the branch, so `dist` is left untouched 
+/// and the push is never forced.
+/// 
+/// On success, returns the hash of the pushed commit, or None if nothing changed so there was nothing to push
+/// 
+/// # Example
+/// ```no_run
+/// # use trunk_ghpages::merge::push_dir_to_subdir;
+/// # use trunk_ghpages::{cli::Options, push::Commit};
+/// # let opts = Options::default();
+/// # let commit = Commit { message: "Update gh-pages".to_string(), date: None };
+/// let cwd = std::env::current_dir()?;
+/// push_dir_to_subdir("https://github.com/FradulentUser/MyRepo.git", &cwd.join("dist"), "gh-pages", "v1.2.0", &commit, &opts)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn push_dir_to_subdir(remote_origin: &str, dist: &Path, branch: &str, subdir: &str, commit: &Commit, opts: &Options) -> Result<Option<String>, Error> {
+    let work_dir = create_temp_dir(opts)?;
+    let res = merge_and_push(remote_origin, dist, &work_dir, branch, subdir, commit, opts);
+    // the branch may already be updated, so a leftover temporary clone doesn't fail the deploy
+    if let Err(e) = remove_dir_all(&work_dir) {
+        log::warn(format!("Could not remove the temporary clone {}: {}", work_dir.display(), e));
+    }
+    res
+}
+
+fn merge_and_push(remote_origin: &str, dist: &Path, work_dir: &Path, branch: &str, subdir: &str, commit: &Commit, opts: &Options) -> Result<Option<String>, Error> {
+    let staging = Instant::now();
+    git(work_dir, &["init"], opts)?;
+    set_origin(work_dir, remote_origin, opts)?;
+
+    // start from the current contents of the branch, if it exists yet
+    let branch_exists = !opts.offline && fetch_remote_branch(work_dir, branch, opts)?;
+    if branch_exists {
+        git(work_dir, &["reset","-q","--hard","FETCH_HEAD"], opts)?;
+    }
+
+    let target = work_dir.join(subdir);
+    if target.exists() {
+        remove_dir_all(&target)?;
+    }
+    stage::copy_deployed_files(dist, &target, opts)?;
+    install_lfs(&target, opts)?;
+
+    redirect_root(work_dir, subdir, opts)?;
+    if opts.pages_branch_readme {
+        seed_branch_readme(work_dir, branch_exists.then_some("HEAD"), branch, opts)?;
+    }
+
+    add_all(work_dir, opts)?;
+    let unchanged = output_step(Command::new(opts.git_bin())
+        .current_dir(work_dir)
+        .args(["diff","--cached","--quiet"]))?
+        .status
+        .success();
+    log::add_timing("staging", staging.elapsed());
+    if unchanged {
+        log::warn(format!("Nothing changed in {}/ of branch '{}', so there is nothing to deploy", subdir, branch));
+        return Ok(None)
+    }
+
+    let committing = Instant::now();
+    let mut cmd = Command::new(opts.git_bin());
+    if let Some(date) = &commit.date {
+        cmd.env("GIT_AUTHOR_DATE", date).env("GIT_COMMITTER_DATE", date);
+    }
+    run_step(cmd.current_dir(work_dir).args(["commit","-m",&commit.message]), opts)?;
+    let truncated = match opts.max_history {
+        Some(max) => truncate_history(work_dir, max.get(), opts)?,
+        None => false,
+    };
+    log::add_timing("commit", committing.elapsed());
+    if truncated && !opts.offline && !opts.yes {
+        confirm_force_push(remote_origin, branch)?;
+    }
+    // the push is the last step that can fail, so a failed deploy never updated the branch
+    let sha = head_sha(work_dir, opts)?;
+    if !opts.offline {
+        let pushing = Instant::now();
+        // a truncated history no longer contains the commits of the remote branch
+        let refspec = format!("{}HEAD:refs/heads/{}", if truncated { "+" } else { "" }, branch);
+        push_step(remote_git(work_dir, opts)
+            .args(["push","origin",&refspec]), branch, opts)?;
+        log::add_timing("push", pushing.elapsed());
+    }
+    Ok(Some(sha))
+}
+
+fn git(dir: &Path, args: &[&str], opts: &Options) -> Result<(), Error> {
+    run_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(args), opts)?;
+    Ok(())
+}
+
+/// Checks that a --target-subdir or --version-dir is a relative path inside of the branch, 
+/// returning it without surrounding slashes
+pub fn validate_subdir(subdir: &str) -> Result<&str, Error> {
+    let trimmed = subdir.trim_matches(['/', '\\']);
+    let escapes = trimmed.split(['/', '\\']).any(|segment| segment == ".." || segment == "." || segment == ".git");
+    if trimmed.is_empty() || escapes || Path::new(trimmed).is_absolute() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("'{}' is not a valid subdirectory to deploy into", subdir)))
+    }
+    Ok(trimmed)
+}
+
+/// Writes the root redirect of the branch checked out at `dir`, which points to the newest version folder with --version-dir, 
+/// or to `subdir` with --root-redirect
+pub(crate) fn redirect_root(dir: &Path, subdir: &str, opts: &Options) -> Result<(), Error> {
+    let target = match (opts.version_dir.is_some(), opts.root_redirect) {
+        (true, _) => latest_version(dir)?,
+        (false, true) => Some(subdir.to_string()),
+        (false, false) => None,
+    };
+    match target {
+        Some(target) => write_root_redirect(dir, &format!("{}/", target), opts.force),
+        None => Ok(()),
+    }
+}
+
+/// Writes an index.html to the root of `dir` that redirects to `target`. 
+/// An existing index.html that wasn't generated by trunk-ghpages is only replaced when `force` is set.
+pub fn write_root_redirect(dir: &Path, target: &str, force: bool) -> Result<(), Error> {
+    let index_path = dir.join("index.html");
+    let generated = read_to_string(&index_path).map_or(true, |html| html.contains(GENERATED_MARKER));
+    if !generated && !force {
+        log::warn("Not writing a redirect to the root index.html of the branch because it already exists. Pass --force to replace it");
+        return Ok(())
+    }
+    write(index_path, redirect_html(target))
+}
+
+/// A minimal HTML page that immediately redirects to `target`
+pub fn redirect_html(target: &str) -> String {
+    let target = target.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+    format!("<!DOCTYPE html>\n{}\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"0; url={}\">\n\
+        <link rel=\"canonical\" href=\"{}\">\n<title>Redirecting</title>\n</head>\n<body>\n<a href=\"{}\">Redirecting to {}</a>\n</body>\n</html>\n",
+        GENERATED_MARKER, target, target, target, target)
+}
+
+/// Finds the newest version folder (such as `v1.2.0` or `2.0`) at the root of `dir`
+fn latest_version(dir: &Path) -> Result<Option<String>, Error> {
+    let mut versions: Vec<String> = dir.read_dir()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter(|name| name.trim_start_matches('v').starts_with(|c: char| c.is_ascii_digit()))
+        .collect();
+    versions.sort_by(|a, b| compare_versions(a, b));
+    Ok(versions.pop())
+}
+
+/// Compares version names like `v1.10.0` and `v1.9.2` by their numeric parts
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| -> Vec<u64> {
+        version.trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .map(|part| part.chars().take_while(char::is_ascii_digit).collect::<String>().parse().unwrap_or(0))
+            .collect()
+    };
+    parts(a).cmp(&parts(b)).then_with(|| a.cmp(b))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_html_escapes_the_target() {
+        let html = redirect_html("/a\"b/?x=1&y=<2>");
+        assert!(html.starts_with(&format!("<!DOCTYPE html>\n{}\n", GENERATED_MARKER)), "{}", html);
+        assert!(html.contains("<meta http-equiv=\"refresh\" content=\"0; url=/a&quot;b/?x=1&amp;y=&lt;2>\">"), "{}", html);
+        assert!(html.contains("<a href=\"/a&quot;b/?x=1&amp;y=&lt;2>\">"), "{}", html);
+        assert!(!html.contains("a\"b") && !html.contains("&y") && !html.contains("<2"), "{}", html);
+    }
+
+    #[test]
+    fn root_redirect_only_replaces_a_user_index_html_with_force() {
+        let _lock = log::test_lock();
+        let dir = std::env::temp_dir().join(format!("trunk-ghpages-redirect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let index_path = dir.join("index.html");
+
+        write_root_redirect(&dir, "my-app/", false).unwrap();
+        let created = read_to_string(&index_path).unwrap();
+        // a generated redirect is updated without --force
+        write_root_redirect(&dir, "v2/", false).unwrap();
+        let updated = read_to_string(&index_path).unwrap();
+        write(&index_path, "<h1>My own page</h1>").unwrap();
+        let warnings = log::warning_count();
+        write_root_redirect(&dir, "v3/", false).unwrap();
+        let kept = read_to_string(&index_path).unwrap();
+        let warned = log::warning_count() - warnings;
+        write_root_redirect(&dir, "v3/", true).unwrap();
+        let forced = read_to_string(&index_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(created, redirect_html("my-app/"));
+        assert_eq!(updated, redirect_html("v2/"));
+        assert_eq!(kept, "<h1>My own page</h1>");
+        assert_eq!(warned, 1);
+        assert_eq!(forced, redirect_html("v3/"));
+    }
+}
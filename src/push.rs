@@ -0,0 +1,979 @@
+use std::{fs::{read_to_string, remove_dir_all}, io::{stderr, stdin, Error, ErrorKind, IsTerminal, Write}, path::Path, process::{Command, ExitStatus, Output, Stdio}, time::{Instant, SystemTime, UNIX_EPOCH}};
+
+use crate::{cli::{Options, OutputFormat}, log::{self, redact}, create_temp_dir, stage, write_branch_notice, BRANCH_NOTICE_FILE};
+
+/// The deploy commit to create
+pub struct Commit {
+    pub message: String,
+    /// Date used as both the author and committer date, instead of the current time
+    pub date: Option<String>,
+}
+
+/// Gets the date of the deploy commit, or None to let git use the current time: 
+/// the committer date of HEAD in the source repo at `cwd` with `--date-from-source`, 
+/// moved into the `--commit-tz` timezone, or UTC if none is given, so the commit is the same on machines in any timezone
+pub fn commit_date(cwd: &Path, opts: &Options) -> Result<Option<String>, Error>{
+    Ok(match (opts.date_from_source, &opts.commit_tz) {
+        (false, None) => None,
+        // git's internal date format is the unix timestamp followed by the offset
+        (true, tz) => Some(format!("{} {}", source_commit_date(cwd, "%ct", opts)?, tz.as_deref().unwrap_or("+0000"))),
+        (false, Some(tz)) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            Some(format!("{} {}", now.as_secs(), tz))
+        }
+    })
+}
+
+/// Gets the committer date of HEAD in the source repo at `cwd` in the given `git show` format, like `%ct`
+fn source_commit_date(cwd: &Path, format: &str, opts: &Options) -> Result<String, Error>{
+    let output = output_step(Command::new(opts.git_bin())
+        .current_dir(cwd)
+        .args(["show","-s",&format!("--format={}", format),"HEAD"]))?;
+    ensure_success(Ok(output.status))?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Gets the git trailers linking a deploy commit back to HEAD of the source repo at `cwd`, i.e. `Source-Commit: <sha>` 
+/// and, unless HEAD is detached, `Source-Ref: <ref>`
+pub fn source_trailers(cwd: &Path, opts: &Options) -> Result<String, Error>{
+    let mut trailers = format!("Source-Commit: {}", head_sha(cwd, opts)?);
+    let output = output_step(Command::new(opts.git_bin())
+        .current_dir(cwd)
+        .args(["symbolic-ref","-q","HEAD"]))?;
+    if output.status.success() {
+        trailers.push_str(&format!("\nSource-Ref: {}", String::from_utf8_lossy(&output.stdout).trim()));
+    }
+    Ok(trailers)
+}
+
+/// Commits and force pushes the contents of the specified directory to the given branch of the remote origin
+/// 
+/// If the branch already has commits on the remote, the user is asked to confirm that its history
+/// will be replaced, unless `opts.yes` is set.
+/// 
+/// On success, returns the hash of the pushed commit
+/// 
+/// # Example
+/// ```no_run
+/// # use trunk_ghpages::push::push_dir_to_branch;
+/// # use trunk_ghpages::{cli::Options, push::Commit};
+/// # let opts = Options::default();
+/// # let commit = Commit { message: "Update gh-pages".to_string(), date: None };
+/// let cwd = std::env::current_dir()?;
+/// let commit_sha = push_dir_to_branch("https://github.com/FradulentUser/MyRepo.git", &cwd.join("dist"), "gh-pages", &commit, &opts)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn push_dir_to_branch(remote_origin: &str, dir: &Path, branch: &str, commit: &Commit, opts: &Options) -> Result<String, Error>{
+    let res = commit_and_push(remote_origin, dir, branch, commit, opts);
+
+    // clean up the temporary repo even if a step failed or the push was aborted
+    if dir.join(".git").exists() {
+        clean_up_git_dir(dir, opts);
+    }
+    res
+}
+
+/// Removes the `.git` directory of the temporary repo at `dir` after deploying, like `remove_git_dir`, 
+/// but only warns when that fails, since the branch may already be updated by then. 
+/// With `--trash-git` and no working trash utility, the directory is deleted instead.
+fn clean_up_git_dir(dir: &Path, opts: &Options){
+    let git_dir = dir.join(".git");
+    let res = match opts.trash_git {
+        true => move_to_trash(&git_dir).or_else(|_| {
+            log::warn(format!("Could not move {} to the trash, since no trash utility was found. Deleting it instead", git_dir.display()));
+            remove_dir_all(&git_dir)
+        }),
+        false => remove_dir_all(&git_dir),
+    };
+    if let Err(e) = res {
+        log::warn(format!("Could not remove the temporary {}: {}. Remove it with --repair", git_dir.display(), e));
+    }
+}
+
+/// Gives the branch a `README.md` saying it is generated, for `--pages-branch-readme`, but only once: 
+/// `dir` gets the notice when the branch is new (`rev` is None), and otherwise keeps the `README.md` of `rev`, 
+/// the existing branch, if it has one. Nothing is done if `dir` already has a `README.md`.
+pub fn seed_branch_readme(dir: &Path, rev: Option<&str>, branch: &str, opts: &Options) -> Result<(), Error>{
+    if dir.join(BRANCH_NOTICE_FILE).exists() {
+        return Ok(())
+    }
+    let Some(rev) = rev else {
+        return write_branch_notice(dir, branch)
+    };
+    let has_readme = output_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["cat-file","-e",&format!("{}:{}", rev, BRANCH_NOTICE_FILE)]))?
+        .status
+        .success();
+    if has_readme {
+        run_step(Command::new(opts.git_bin())
+            .current_dir(dir)
+            .args(["checkout",rev,"--",BRANCH_NOTICE_FILE]), opts)?;
+    }
+    Ok(())
+}
+
+/// Removes a `.git` directory left behind in `dist` by a deploy that was interrupted, for `--repair`. 
+/// Asks for confirmation first, unless `opts.yes` is set. Returns whether there was one to remove.
+pub fn remove_stray_git(dist: &Path, opts: &Options) -> Result<bool, Error>{
+    let git_dir = dist.join(".git");
+    if !git_dir.is_dir() {
+        return Ok(false)
+    }
+    if !opts.yes {
+        confirm(&format!("{} will be deleted.", git_dir.display()),
+            "Pass --yes to confirm the deletion in non-interactive contexts", "Aborted repair")?;
+    }
+    remove_git_dir(dist, opts)?;
+    Ok(true)
+}
+
+/// Deletes the `.git` directory of the temporary repo at `dir`, or moves it to the trash with `--trash-git`
+fn remove_git_dir(dir: &Path, opts: &Options) -> Result<(), Error>{
+    match opts.trash_git {
+        true => move_to_trash(&dir.join(".git")),
+        false => remove_dir_all(dir.join(".git")),
+    }
+}
+
+fn commit_and_push(remote_origin: &str, dir: &Path, branch: &str, commit: &Commit, opts: &Options) -> Result<String, Error>{
+    let staging = Instant::now();
+    run_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .arg("init"), opts)?;
+    set_origin(dir, remote_origin, opts)?;
+
+    stage::write_excludes(dir, opts)?;
+    install_lfs(dir, opts)?;
+
+    // offline deploys skip everything that talks to the remote, so the commit can't be based on the existing branch
+    let branch_exists = !opts.offline && (!opts.yes || opts.amend || opts.pages_branch_readme) && fetch_remote_branch(dir, branch, opts)?;
+    if branch_exists && !opts.yes {
+        confirm_force_push(remote_origin, branch)?;
+    }
+
+    // amending keeps the branch at exactly one commit only if that's all it has, 
+    // otherwise a new root commit is created just like on the first deploy
+    let amend = opts.amend && branch_exists && is_root_commit(dir, "FETCH_HEAD", opts)?;
+    if amend {
+        run_step(Command::new(opts.git_bin())
+            .current_dir(dir)
+            .args(["reset","--soft","FETCH_HEAD"]), opts)?;
+    }
+
+    if opts.pages_branch_readme {
+        seed_branch_readme(dir, branch_exists.then_some("FETCH_HEAD"), branch, opts)?;
+    }
+    match opts.fast {
+        true => update_index(dir, opts)?,
+        false => add_all(dir, opts)?,
+    }
+    log::add_timing("staging", staging.elapsed());
+
+    let committing = Instant::now();
+    match opts.fast {
+        true => commit_tree(dir, branch, commit, opts)?,
+        false => commit_index(dir, branch, commit, amend, opts)?,
+    }
+    log::add_timing("commit", committing.elapsed());
+
+    // the push is the last step that can fail, so a failed deploy never updated the branch
+    let sha = head_sha(dir, opts)?;
+    if !opts.offline {
+        let pushing = Instant::now();
+        push_step(remote_git(dir, opts)
+            .args(["push","-uf","origin",branch]), branch, opts)?;
+        log::add_timing("push", pushing.elapsed());
+    }
+    Ok(sha)
+}
+
+/// Commits the staged site in the repo at `dir` with `git commit`, amending HEAD if `amend` is set, and creates `branch` at it
+fn commit_index(dir: &Path, branch: &str, commit: &Commit, amend: bool, opts: &Options) -> Result<(), Error>{
+    let commit_args: &[&str] = match amend {
+        true => &["commit","--amend","--reset-author","-m",&commit.message],
+        false => &["commit","-am",&commit.message],
+    };
+    let cmds_args: [&[&str]; 2] = [commit_args,
+        &["branch",branch]];
+
+    for args in cmds_args{
+        let mut cmd = Command::new(opts.git_bin());
+        if let Some(date) = &commit.date {
+            cmd.env("GIT_AUTHOR_DATE", date).env("GIT_COMMITTER_DATE", date);
+        }
+        run_step(cmd
+            .current_dir(dir)
+            .args(args), opts)?;
+    }
+    Ok(())
+}
+
+/// Stages everything in the repo at `dir` for `--fast`, with a single `git update-index` instead of `git add -A`. 
+/// 
+/// The paths `git add -A` would stage are listed with `ls-files`, which respects the excludes the same way, 
+/// and piped to `update-index`, so file modes, symlinks and LFS filters are handled just like by `git add`.
+fn update_index(dir: &Path, opts: &Options) -> Result<(), Error>{
+    let paths = plumbing_step(dir, &["ls-files","-z","--cached","--others","--exclude-standard"], &[], None, opts)?;
+    plumbing_step(dir, &["update-index","--add","--remove","-z","--stdin"], &paths, None, opts)?;
+    Ok(())
+}
+
+/// Turns the index of the repo at `dir` into the deploy commit for `--fast`, with `write-tree` and `commit-tree` 
+/// instead of `git commit`, and points HEAD and `branch` at it. 
+/// 
+/// Like the commit `git commit` creates in the fresh temporary repo, or when amending the single commit of the branch, 
+/// it's a root commit, with the same tree, message, identity and dates.
+fn commit_tree(dir: &Path, branch: &str, commit: &Commit, opts: &Options) -> Result<(), Error>{
+    let tree = plumbing_step(dir, &["write-tree"], &[], None, opts)?;
+    let tree = String::from_utf8_lossy(&tree).trim().to_string();
+    let sha = plumbing_step(dir, &["commit-tree",&tree,"-m",&commit.message], &[], commit.date.as_deref(), opts)?;
+    let sha = String::from_utf8_lossy(&sha).trim().to_string();
+    for reference in ["HEAD".to_string(), format!("refs/heads/{}", branch)] {
+        run_step(Command::new(opts.git_bin())
+            .current_dir(dir)
+            .args(["update-ref",&reference,&sha]), opts)?;
+    }
+    Ok(())
+}
+
+/// Runs the plumbing command `git <args>` in the repo at `dir` with `input` on its stdin, recording it as a step, 
+/// and returns its stdout. With a `date`, it's used as the author and committer date.
+fn plumbing_step(dir: &Path, args: &[&str], input: &[u8], date: Option<&str>, opts: &Options) -> Result<Vec<u8>, Error>{
+    let mut cmd = Command::new(opts.git_bin());
+    if let Some(date) = date {
+        cmd.env("GIT_AUTHOR_DATE", date).env("GIT_COMMITTER_DATE", date);
+    }
+    cmd.current_dir(dir).args(args).stdin(Stdio::piped()).stdout(Stdio::piped());
+    let start = Instant::now();
+    let mut child = cmd.spawn()?;
+    // none of the commands print anything before they've read all of their input
+    child.stdin.take().ok_or_else(|| Error::other(format!("Could not write to git {}", args[0])))?.write_all(input)?;
+    let output = child.wait_with_output()?;
+    record(&cmd, start, output.status.code());
+    ensure_success(Ok(output.status))?;
+    Ok(output.stdout)
+}
+
+/// Gets the hash of the HEAD commit of the repo at `dir`
+pub fn head_sha(dir: &Path, opts: &Options) -> Result<String, Error>{
+    let output = output_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["rev-parse","HEAD"]))?;
+    ensure_success(Ok(output.status))?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Rewrites the history of HEAD in the repo at `dir` to keep only its last `max` commits, for `--max-history`, 
+/// by turning the oldest kept commit into a new root commit and recreating the ones after it on top. 
+/// The commits are otherwise copied exactly, keeping their trees, messages, authors and dates. Signatures are dropped, 
+/// since they'd no longer match. Returns whether the history was truncated, which means it has to be force-pushed.
+pub fn truncate_history(dir: &Path, max: u32, opts: &Options) -> Result<bool, Error>{
+    let git_output = |args: &[&str]| -> Result<String, Error> {
+        let output = output_step(Command::new(opts.git_bin()).current_dir(dir).args(args))?;
+        ensure_success(Ok(output.status))?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let count: u32 = git_output(&["rev-list","--count","HEAD"])?.trim().parse().map_err(Error::other)?;
+    if count <= max {
+        return Ok(false)
+    }
+
+    let mut parent: Option<String> = None;
+    for sha in git_output(&["rev-list","--reverse",&format!("--max-count={}", max),"HEAD"])?.lines() {
+        let commit = reparent_commit(&git_output(&["cat-file","commit",sha])?, parent.as_deref());
+        let sha = plumbing_step(dir, &["hash-object","-t","commit","-w","--stdin"], commit.as_bytes(), None, opts)?;
+        parent = Some(String::from_utf8_lossy(&sha).trim().to_string());
+    }
+    if let Some(head) = parent {
+        run_step(Command::new(opts.git_bin())
+            .current_dir(dir)
+            .args(["reset","-q","--soft",&head]), opts)?;
+    }
+    log::debug(format!("Truncated the history from {} to {} commits", count, max));
+    Ok(true)
+}
+
+/// Replaces the parents of the raw commit object `commit` with `parent`, or makes it a root commit without one, 
+/// leaving out any signature
+fn reparent_commit(commit: &str, parent: Option<&str>) -> String{
+    let (header, message) = commit.split_once("\n\n").unwrap_or((commit, ""));
+    let mut out = String::with_capacity(commit.len());
+    let mut in_signature = false;
+    for line in header.lines() {
+        // the lines of multi-line headers like gpgsig after the first start with a space
+        in_signature = line.starts_with("gpgsig") || (in_signature && line.starts_with(' '));
+        if in_signature || line.starts_with("parent ") {
+            continue
+        }
+        out.push_str(line);
+        out.push('\n');
+        if let (true, Some(parent)) = (line.starts_with("tree "), parent) {
+            out.push_str(&format!("parent {}\n", parent));
+        }
+    }
+    out.push('\n');
+    out.push_str(message);
+    out
+}
+
+/// Points the origin remote of the repo at `dir` to `remote_origin`, adding it if the repo doesn't have one yet. 
+/// A repo left behind by an earlier deploy already has an origin, which `git remote add` would fail on.
+pub fn set_origin(dir: &Path, remote_origin: &str, opts: &Options) -> Result<(), Error>{
+    let has_origin = output_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["remote","get-url","origin"]))?
+        .status
+        .success();
+    run_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["remote",if has_origin { "set-url" } else { "add" },"origin",remote_origin]), opts)?;
+    Ok(())
+}
+
+/// Creates a git command in the repo at `dir` for talking to the remote. 
+/// 
+/// With `--credential-helper`, the command gets a credential helper answering with `--token`, 
+/// which replaces any configured helpers so the token isn't stored by them. The helper only exists 
+/// for this command, and reads the token from its environment so it isn't part of the arguments either.
+pub fn remote_git(dir: &Path, opts: &Options) -> Command{
+    let mut cmd = Command::new(opts.git_bin());
+    cmd.current_dir(dir);
+    if let (Some(token), true) = (&opts.token, opts.credential_helper) {
+        cmd.args(["-c","credential.helper=",
+            "-c","credential.helper=!f() { test \"$1\" = get && echo username=x-access-token && echo \"password=$TRUNK_GHPAGES_TOKEN\"; }; f"])
+            .env("TRUNK_GHPAGES_TOKEN", token);
+    }
+    cmd
+}
+
+/// Enables Git LFS in the repo at `dir` if its `.gitattributes` tracks any files with LFS, 
+/// or warns that the files will be committed as regular blobs when git-lfs isn't installed.
+pub fn install_lfs(dir: &Path, opts: &Options) -> Result<(), Error>{
+    let uses_lfs = read_to_string(dir.join(".gitattributes")).is_ok_and(|attributes| attributes.contains("filter=lfs"));
+    if !uses_lfs {
+        return Ok(())
+    }
+
+    let lfs_installed = output_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["lfs","version"]))
+        .is_ok_and(|output| output.status.success());
+    if !lfs_installed {
+        log::warn("The deployed files include Git LFS patterns, but git-lfs is not installed, \
+            so files matching them will be committed as regular files");
+        return Ok(())
+    }
+
+    run_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["lfs","install","--local"]), opts)?;
+    Ok(())
+}
+
+/// Stages every change in the repo at `dir`, like `git add -A`. 
+/// 
+/// With `--add-batch-size`, the changed paths are listed first and staged with one `git add` per batch of that many paths instead, 
+/// which keeps a single git process from running out of memory or past the command line limits on huge sites. 
+/// The listed paths are the untracked, modified and deleted ones that aren't ignored, so the staged tree is the same either way.
+pub fn add_all(dir: &Path, opts: &Options) -> Result<(), Error>{
+    let batch_size = match opts.add_batch_size {
+        Some(batch_size) => batch_size.get(),
+        None => {
+            run_step(Command::new(opts.git_bin()).current_dir(dir).args(["add","-A"]), opts)?;
+            return Ok(())
+        }
+    };
+
+    let output = output_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["ls-files","-z","--others","--modified","--deleted","--exclude-standard"]))?;
+    ensure_success(Ok(output.status))?;
+    let listed = String::from_utf8_lossy(&output.stdout);
+    // deleted files are listed as modified too
+    let mut paths: Vec<&str> = listed.split('\0').filter(|path| !path.is_empty()).collect();
+    paths.sort_unstable();
+    paths.dedup();
+
+    for batch in paths.chunks(batch_size) {
+        run_step(Command::new(opts.git_bin())
+            .current_dir(dir)
+            .args(["add","-A","--"])
+            .args(batch), opts)?;
+    }
+    Ok(())
+}
+
+/// Whether `rev` in the repo at `dir` is a commit without any parents. 
+/// 
+/// This reads the raw commit object, so it also works for shallow fetches where the parents are missing.
+fn is_root_commit(dir: &Path, rev: &str, opts: &Options) -> Result<bool, Error>{
+    let output = output_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["cat-file","-p",rev]))?;
+    ensure_success(Ok(output.status))?;
+    Ok(!String::from_utf8_lossy(&output.stdout).lines().any(|line| line.starts_with("parent ")))
+}
+
+/// Fetches the given branch from origin into the repo at `dir`, only fetching the last `opts.depth()` commits
+/// unless the depth is 0. 
+/// 
+/// A shallow fetch is enough here: the throwaway push of the whole branch discards the fetched commits, 
+/// and while merge mode keeps the fetched commit as the parent of the pushed one, the remote already has it, 
+/// so pushing afterwards can't fail with "shallow update not allowed".
+/// 
+/// Returns Ok(true) if the branch exists on the remote, or Ok(false) if it doesn't.
+pub fn fetch_remote_branch(dir: &Path, branch: &str, opts: &Options) -> Result<bool, Error>{
+    let mut cmd = remote_git(dir, opts);
+    cmd.arg("fetch");
+    if opts.depth() > 0 {
+        cmd.arg(format!("--depth={}", opts.depth()));
+    }
+    let output = output_step(cmd.args(["origin",branch]))?;
+    if output.status.success() {
+        return Ok(true)
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("couldn't find remote ref") {
+        Ok(false)
+    } else {
+        Err(Error::other(stderr.trim().to_string()))
+    }
+}
+
+/// Gets the source commit the tip of `branch` on the remote at `remote_origin` was deployed from, according to its 
+/// `Source-Commit` trailer, or None if the branch doesn't exist or its tip has no trailer. The tip is fetched into a temporary repo.
+pub fn last_deployed_source(remote_origin: &str, branch: &str, opts: &Options) -> Result<Option<String>, Error>{
+    let dir = create_temp_dir(opts)?;
+    let res = run_step(Command::new(opts.git_bin()).current_dir(&dir).args(["init","-q"]), opts)
+        .and_then(|_| set_origin(&dir, remote_origin, opts))
+        .and_then(|()| fetch_remote_branch(&dir, branch, opts))
+        .and_then(|exists| {
+            if !exists {
+                return Ok(None)
+            }
+            let output = output_step(Command::new(opts.git_bin())
+                .current_dir(&dir)
+                .args(["log","-1","--format=%B","FETCH_HEAD"]))?;
+            ensure_success(Ok(output.status))?;
+            Ok(String::from_utf8_lossy(&output.stdout).lines()
+                .rev()
+                .find_map(|line| Some(line.strip_prefix("Source-Commit:")?.trim().to_string())))
+        });
+    remove_dir_all(&dir)?;
+    res
+}
+
+/// Whether `branch` exists on the remote at `remote_origin`, checked with `git ls-remote` from `cwd` without fetching anything
+pub fn remote_branch_exists(cwd: &Path, remote_origin: &str, branch: &str, opts: &Options) -> Result<bool, Error>{
+    let output = output_step(remote_git(cwd, opts)
+        .args(["ls-remote","--heads",remote_origin,branch]))?;
+    ensure_success(Ok(output.status))?;
+    let head = format!("refs/heads/{}", branch);
+    Ok(String::from_utf8_lossy(&output.stdout).lines().any(|line| line.split_whitespace().nth(1) == Some(head.as_str())))
+}
+
+/// Checks that the remote at `remote_origin` can be read with the configured credentials, by running `git ls-remote` from `cwd`. 
+/// Nothing is modified, and git isn't allowed to prompt for credentials, so missing ones fail instead of hanging. 
+/// Returns an error with git's message if the remote can't be accessed.
+pub fn check_remote_access(cwd: &Path, remote_origin: &str, opts: &Options) -> Result<(), Error>{
+    let output = output_step(remote_git(cwd, opts)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .args(["ls-remote","--heads",remote_origin]))?;
+    ensure_success(Ok(output.status)).map_err(|e| {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = if stderr.trim().is_empty() { e.to_string() } else { stderr.trim().to_string() };
+        Error::new(ErrorKind::PermissionDenied, format!("Could not access {}: {}", redact(remote_origin), message))
+    })?;
+    Ok(())
+}
+
+/// Lists the branches of the remote at `remote_origin` with `git ls-remote`, run from `cwd`
+pub fn remote_branches(cwd: &Path, remote_origin: &str, opts: &Options) -> Result<Vec<String>, Error>{
+    let output = output_step(remote_git(cwd, opts)
+        .args(["ls-remote","--heads",remote_origin]))?;
+    ensure_success(Ok(output.status))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1)?.strip_prefix("refs/heads/"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Deletes `branches` from the remote at `remote_origin` with a single push from `cwd`. 
+/// Asks for confirmation first, unless `opts.yes` is set.
+pub fn delete_remote_branches(cwd: &Path, remote_origin: &str, branches: &[String], opts: &Options) -> Result<(), Error>{
+    if !opts.yes {
+        confirm(&format!("{} branch(es) of {} will be deleted: {}.", branches.len(), redact(remote_origin), branches.join(", ")),
+            "Pass --yes to confirm the deletion in non-interactive contexts", "Aborted pruning")?;
+    }
+    run_step(remote_git(cwd, opts)
+        .args(["push",remote_origin,"--delete"])
+        .args(branches), opts)?;
+    Ok(())
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any number of characters and `?` exactly one
+pub fn glob_match(pattern: &str, name: &str) -> bool{
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and of the character in `name` it currently extends to, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Asks the user to confirm that the history of `branch` on the remote will be replaced. 
+/// 
+/// Returns an error if the user declines, or if stdout isn't a terminal so nobody can be asked.
+pub fn confirm_force_push(remote_origin: &str, branch: &str) -> Result<(), Error>{
+    confirm(&format!("Branch '{}' of {} already has commits, and force-pushing will replace its history.", branch, redact(remote_origin)),
+        "Pass --yes to confirm the force-push in non-interactive contexts", &format!("Aborted force-push to branch '{}'", branch))
+}
+
+/// Prints `warning` and asks the user to continue, returning an error with `aborted` if they don't. 
+/// Without a terminal, the error explains how to confirm with `hint` instead.
+fn confirm(warning: &str, hint: &str, aborted: &str) -> Result<(), Error>{
+    // the prompt goes to stderr, so that it stays out of the `--format json` output on stdout
+    if !stdin().is_terminal() || !stderr().is_terminal() {
+        return Err(Error::other(format!("{} {}", warning, hint)))
+    }
+
+    eprint!("{} Continue? [y/N] ", warning);
+    stderr().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+
+    match answer.trim() {
+        "y" | "Y" | "yes" => Ok(()),
+        _ => Err(Error::other(aborted.to_string())),
+    }
+}
+
+/// Moves `path` to the system trash by running the platform's trash utility, so it can still be recovered. 
+/// If no trash utility works, `path` is left in place and an error is returned.
+fn move_to_trash(path: &Path) -> Result<(), Error>{
+    let path_str = path.to_string_lossy();
+    let quoted = path_str.replace('\'', "''");
+    let windows_script = format!("Add-Type -AssemblyName Microsoft.VisualBasic; \
+        [Microsoft.VisualBasic.FileIO.FileSystem]::DeleteDirectory('{}', 'OnlyErrorDialogs', 'SendToRecycleBin')", quoted);
+    let mac_script = format!("tell application \"Finder\" to delete POSIX file \"{}\"", path_str.replace('"', "\\\""));
+
+    let candidates: Vec<(&str, Vec<&str>)> = if cfg!(windows) {
+        vec![("powershell", vec!["-NoProfile","-Command",&windows_script])]
+    } else if cfg!(target_os = "macos") {
+        vec![("trash", vec![&path_str]), ("osascript", vec!["-e",&mac_script])]
+    } else {
+        vec![("gio", vec!["trash",&path_str]), ("trash-put", vec![&path_str]), ("kioclient5", vec!["move",&path_str,"trash:/"])]
+    };
+
+    for (program, args) in candidates {
+        let trashed = Command::new(program).args(args).output().is_ok_and(|output| output.status.success());
+        if trashed && !path.exists() {
+            return Ok(())
+        }
+    }
+    Err(Error::new(ErrorKind::NotFound, format!(
+        "Could not move {} to the trash, since no trash utility was found. It was left in place, and can be removed manually", path.display())))
+}
+
+/// Runs `cmd` and checks that it succeeded like `ensure_success`, recording its arguments, duration and exit code as a step.
+/// With `--format json`, the output of the command goes to stderr so that stdout only has the JSON.
+pub fn run_step(cmd: &mut Command, opts: &Options) -> Result<ExitStatus, Error>{
+    if opts.format == OutputFormat::Json {
+        cmd.stdout(stderr());
+    }
+    let start = Instant::now();
+    let res = cmd.status();
+    record(cmd, start, res.as_ref().ok().and_then(ExitStatus::code));
+    ensure_success(res)
+}
+
+/// Runs the `git push` in `cmd` to `branch` like `run_step`, but captures its stderr, so that a rejected push can be explained 
+/// with [explain_push_error] instead of just failing with git's exit code. The output of git is still printed after the push.
+pub fn push_step(cmd: &mut Command, branch: &str, opts: &Options) -> Result<(), Error>{
+    match opts.format {
+        OutputFormat::Json => cmd.stdout(stderr()),
+        OutputFormat::Text => cmd.stdout(Stdio::inherit()),
+    };
+    let start = Instant::now();
+    let res = cmd.stderr(Stdio::piped()).output();
+    record(cmd, start, res.as_ref().ok().and_then(|output| output.status.code()));
+    let output = res?;
+    let git_stderr = String::from_utf8_lossy(&output.stderr);
+    eprint!("{}", redact(&git_stderr));
+
+    match (output.status.success(), explain_push_error(&git_stderr, branch)) {
+        (true, _) => Ok(()),
+        (false, Some(explanation)) => Err(Error::new(ErrorKind::PermissionDenied, explanation)),
+        (false, None) => ensure_success(Ok(output.status)).map(|_| ()),
+    }
+}
+
+/// Translates the stderr of a `git push` to `branch` that was rejected because the branch is protected, 
+/// or because the credentials aren't allowed to push, into a message saying how to fix it. 
+/// Returns None for every other error, which is left as git reported it.
+///
+/// # Example:
+/// ```
+/// # use trunk_ghpages::push::explain_push_error;
+/// let explanation = explain_push_error("remote: error: GH006: Protected branch update failed for refs/heads/gh-pages.", "gh-pages");
+/// assert!(explanation.is_some_and(|explanation| explanation.contains("is protected")));
+/// ```
+pub fn explain_push_error(stderr: &str, branch: &str) -> Option<String>{
+    let stderr = stderr.to_lowercase();
+    // GitHub reports protected branches and rulesets with GH006 and GH013, GitLab with "protected branch" messages
+    let protected = ["gh006", "gh013", "protected branch", "not allowed to force push", "cannot force-push to this branch"];
+    let denied = ["the requested url returned error: 403", "write access to repository not granted", "not allowed to push code"];
+    if protected.iter().any(|signature| stderr.contains(signature)) {
+        Some(format!("The branch '{}' is protected on the remote, so the push was rejected. \
+            Disable the branch protection for it, or push with a token that is allowed to bypass it", branch))
+    } else if denied.iter().any(|signature| stderr.contains(signature)) || (stderr.contains("permission to") && stderr.contains("denied")) {
+        Some(format!("The remote denied the push to branch '{}', so the credentials used don't have write access. \
+            Push with a token that can write to the repo, such as a GITHUB_TOKEN with `contents: write` permission in GitHub Actions", branch))
+    } else {
+        None
+    }
+}
+
+/// Runs `cmd` capturing its output, recording it as a step like `run_step` without checking that it succeeded
+pub fn output_step(cmd: &mut Command) -> Result<Output, Error>{
+    let start = Instant::now();
+    let res = cmd.output();
+    record(cmd, start, res.as_ref().ok().and_then(|output| output.status.code()));
+    res
+}
+
+fn record(cmd: &Command, start: Instant, exit_code: Option<i32>) {
+    let command = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| redact(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    log::record_step(log::Step { command, duration: start.elapsed(), exit_code });
+}
+
+/// Checks that the installed git is new enough for the git commands the deploy with `opts` runs, 
+/// so an old git fails up front with the version it needs, instead of with a cryptic error halfway through
+pub fn check_git_version(opts: &Options) -> Result<(), Error>{
+    let output = output_step(Command::new(opts.git_bin()).arg("--version"))?;
+    ensure_success(Ok(output.status))?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let Some(version) = parse_git_version(&output) else {
+        log::warn(format!("Could not parse the git version from '{}', so it isn't checked", output.trim()));
+        return Ok(())
+    };
+
+    let (required, feature) = required_git_version(opts);
+    if version < required {
+        return Err(Error::new(ErrorKind::Unsupported, format!("git {}.{}.{} was found, but {} needs at least git {}.{}. Please update git",
+            version.0, version.1, version.2, feature, required.0, required.1)))
+    }
+    Ok(())
+}
+
+/// Parses the output of `git --version`, like `git version 2.39.5` or `git version 2.39.5.windows.1`, into its major, minor and patch version
+pub fn parse_git_version(output: &str) -> Option<(u32, u32, u32)>{
+    let version = output.trim().strip_prefix("git version ")?.split_whitespace().next()?;
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next()?.ok()?;
+    let patch = parts.next().and_then(Result::ok).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+/// The oldest git version supporting every command the deploy with `opts` runs, 
+/// along with the feature needing it for the error message
+fn required_git_version(opts: &Options) -> ((u32, u32, u32), &'static str){
+    if opts.commit_only {
+        // `git worktree remove`
+        ((2, 17, 0), "--commit-only")
+    } else {
+        // `git remote get-url`, used by every deploy
+        ((2, 7, 0), "trunk-ghpages")
+    }
+}
+
+/// Transforms a Result<ExitStatus, Error> so that Ok(ExitStatus) is only returned if the exit status is a success. 
+/// Otherwise, returns an error.
+/// 
+/// # Example:
+/// ```no_run
+/// # use std::process::Command;
+/// # use trunk_ghpages::push::ensure_success;
+/// let success_status = ensure_success(Command::new("ls").status())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn ensure_success(res: Result<ExitStatus, Error>) -> Result<ExitStatus, Error>{
+    let res = res?;
+    res.success().then_some(res).ok_or(Error::other(res.to_string()))
+}
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use super::*;
+    use crate::stage;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git").current_dir(dir).args(args).output().unwrap();
+        assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Creates a fresh repo at `dir` containing a small site, like the temporary repo of a deploy
+    fn site_repo(dir: &Path, opts: &Options) {
+        create_dir_all(dir.join("assets/icons")).unwrap();
+        write(dir.join("index.html"), "<script src=\"/MyRepo/app.js\"></script>\n").unwrap();
+        write(dir.join("app.js"), "init();\n").unwrap();
+        write(dir.join("app.js.map"), "{}\n").unwrap();
+        write(dir.join("assets/icons/favicon.ico"), [0u8, 1, 2, 255]).unwrap();
+        write(dir.join("assets/a b.png"), "png").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{symlink, PermissionsExt};
+            write(dir.join("run.sh"), "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(dir.join("run.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+            symlink("app.js", dir.join("latest.js")).unwrap();
+        }
+        git(dir, &["init","-q"]);
+        git(dir, &["config","user.name","Deployer"]);
+        git(dir, &["config","user.email","deployer@example.com"]);
+        stage::write_excludes(dir, opts).unwrap();
+    }
+
+    #[test]
+    fn fast_commit_matches_porcelain_commit() {
+        let _lock = log::test_lock();
+        let temp = std::env::temp_dir().join(format!("trunk-ghpages-fast-{}", std::process::id()));
+        let (porcelain, fast) = (temp.join("porcelain"), temp.join("fast"));
+        let opts = Options { no_sourcemaps: true, ..Options::default() };
+        let commit = Commit { message: "Update gh-pages".to_string(), date: Some("2026-01-01T12:00:00+00:00".to_string()) };
+
+        site_repo(&porcelain, &opts);
+        add_all(&porcelain, &opts).unwrap();
+        commit_index(&porcelain, "gh-pages", &commit, false, &opts).unwrap();
+        site_repo(&fast, &opts);
+        update_index(&fast, &opts).unwrap();
+        commit_tree(&fast, "gh-pages", &commit, &opts).unwrap();
+
+        let tree = |dir: &Path| git(dir, &["ls-tree","-r","gh-pages"]);
+        let (porcelain_tree, fast_tree) = (tree(&porcelain), tree(&fast));
+        let heads = [&porcelain, &fast].map(|dir| (git(dir, &["rev-parse","HEAD"]), git(dir, &["rev-parse","gh-pages"])));
+        std::fs::remove_dir_all(&temp).unwrap();
+
+        assert!(!porcelain_tree.contains("app.js.map"), "{}", porcelain_tree);
+        assert!(porcelain_tree.contains("assets/a b.png"), "{}", porcelain_tree);
+        assert_eq!(fast_tree, porcelain_tree);
+        // with the same identity and dates, even the commits are the same
+        assert_eq!(heads[1], heads[0]);
+        assert_eq!(heads[0].0, heads[0].1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn trash_git_without_a_trash_utility_still_deploys() {
+        // the trash utilities are found through PATH, so the deploy runs in a copy of the test process whose PATH only has git
+        let Some(temp) = std::env::var_os("TRUNK_GHPAGES_TEST_NO_TRASH").map(std::path::PathBuf::from) else {
+            let temp = std::env::temp_dir().join(format!("trunk-ghpages-trash-{}", std::process::id()));
+            let path = std::env::var_os("PATH").unwrap_or_default();
+            let git_bin = std::env::split_paths(&path).map(|dir| dir.join("git")).find(|git| git.is_file()).unwrap();
+            create_dir_all(temp.join("bin")).unwrap();
+            std::os::unix::fs::symlink(git_bin, temp.join("bin/git")).unwrap();
+            let output = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact","push::tests::trash_git_without_a_trash_utility_still_deploys","--nocapture"])
+                .env("TRUNK_GHPAGES_TEST_NO_TRASH", &temp)
+                .env("PATH", temp.join("bin"))
+                .output()
+                .unwrap();
+            std::fs::remove_dir_all(&temp).unwrap();
+            assert!(output.status.success(), "{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+            assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+            return
+        };
+
+        let (dir, remote) = (temp.join("dist"), temp.join("MyRepo.git"));
+        create_dir_all(&dir).unwrap();
+        write(dir.join("index.html"), "<h1>Site</h1>").unwrap();
+        git(&temp, &["init","-q","--bare",&remote.to_string_lossy()]);
+        for (var, value) in [("GIT_AUTHOR_NAME", "Deployer"), ("GIT_AUTHOR_EMAIL", "deployer@example.com"),
+            ("GIT_COMMITTER_NAME", "Deployer"), ("GIT_COMMITTER_EMAIL", "deployer@example.com")] {
+            std::env::set_var(var, value);
+        }
+        let opts = Options { trash_git: true, yes: true, quiet: true, ..Options::default() };
+        let commit = Commit { message: "Update gh-pages".to_string(), date: None };
+        let warnings = log::warning_count();
+
+        let sha = push_dir_to_branch(&remote.to_string_lossy(), &dir, "gh-pages", &commit, &opts).unwrap();
+        assert_eq!(git(&remote, &["rev-parse","gh-pages"]), sha);
+        assert!(!dir.join(".git").exists());
+        assert_eq!(log::warning_count(), warnings + 1);
+    }
+
+    #[test]
+    fn truncate_history_keeps_the_last_commits() {
+        let _lock = log::test_lock();
+        let dir = std::env::temp_dir().join(format!("trunk-ghpages-truncate-{}", std::process::id()));
+        let opts = Options::default();
+        site_repo(&dir, &opts);
+        for n in 1..=5 {
+            write(dir.join("app.js"), format!("init({});\n", n)).unwrap();
+            git(&dir, &["add","-A"]);
+            git(&dir, &["commit","-q","-m",&format!("Deploy {}", n)]);
+        }
+        let tree = git(&dir, &["rev-parse","HEAD^{tree}"]);
+        let log = git(&dir, &["log","-2","--format=%s %an %ad %T"]);
+
+        let truncated = truncate_history(&dir, 2, &opts).unwrap();
+        let count = git(&dir, &["rev-list","--count","HEAD"]);
+        let (tree_after, log_after) = (git(&dir, &["rev-parse","HEAD^{tree}"]), git(&dir, &["log","--format=%s %an %ad %T"]));
+        let status = git(&dir, &["status","--porcelain"]);
+        let truncated_again = truncate_history(&dir, 2, &opts).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(truncated);
+        assert_eq!(count, "2");
+        assert_eq!(tree_after, tree);
+        assert_eq!(log_after, log);
+        assert_eq!(status, "");
+        assert!(!truncated_again);
+    }
+
+    #[test]
+    fn date_from_source_is_in_utc_unless_a_timezone_is_given() {
+        let _lock = log::test_lock();
+        let temp = std::env::temp_dir().join(format!("trunk-ghpages-date-{}", std::process::id()));
+        let (source, dir) = (temp.join("source"), temp.join("site"));
+        create_dir_all(&source).unwrap();
+        git(&source, &["init","-q"]);
+        let output = Command::new("git").current_dir(&source)
+            .args(["-c","user.name=Author","-c","user.email=author@example.com","commit","-q","--allow-empty","-m","Source"])
+            .env("GIT_COMMITTER_DATE", "2026-03-01T12:00:00+02:00")
+            .output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let opts = Options { date_from_source: true, ..Options::default() };
+        let utc = commit_date(&source, &opts).unwrap();
+        let in_tz = commit_date(&source, &Options { commit_tz: Some("-0530".to_string()), ..opts.clone() }).unwrap();
+        site_repo(&dir, &opts);
+        add_all(&dir, &opts).unwrap();
+        commit_index(&dir, "gh-pages", &Commit { message: "Update gh-pages".to_string(), date: utc.clone() }, false, &opts).unwrap();
+        let dates = git(&dir, &["show","-s","--format=%aI %cI","gh-pages"]);
+        std::fs::remove_dir_all(&temp).unwrap();
+
+        assert_eq!(utc.as_deref(), Some("1772359200 +0000"));
+        assert_eq!(in_tz.as_deref(), Some("1772359200 -0530"));
+        assert_eq!(dates, "2026-03-01T10:00:00+00:00 2026-03-01T10:00:00+00:00");
+    }
+
+    #[test]
+    fn set_origin_replaces_an_existing_origin() {
+        let _lock = log::test_lock();
+        let dir = std::env::temp_dir().join(format!("trunk-ghpages-origin-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        git(&dir, &["init","-q"]);
+        let opts = Options::default();
+
+        let added = set_origin(&dir, "https://github.com/FradulentUser/Old.git", &opts);
+        let first = git(&dir, &["remote","get-url","origin"]);
+        // like a repo left behind by an interrupted deploy
+        let replaced = set_origin(&dir, "https://github.com/FradulentUser/MyRepo.git", &opts);
+        let second = git(&dir, &["remote","get-url","origin"]);
+        let remotes = git(&dir, &["remote"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(added.is_ok(), "{:?}", added);
+        assert_eq!(first, "https://github.com/FradulentUser/Old.git");
+        assert!(replaced.is_ok(), "{:?}", replaced);
+        assert_eq!(second, "https://github.com/FradulentUser/MyRepo.git");
+        assert_eq!(remotes, "origin");
+    }
+
+    #[test]
+    fn explain_push_error_recognizes_branch_protection() {
+        for stderr in [
+            "remote: error: GH006: Protected branch update failed for refs/heads/gh-pages.\nremote: error: Changes must be made through a pull request.",
+            "remote: error: GH013: Repository rule violations found for refs/heads/gh-pages.",
+            "remote: GitLab: You are not allowed to force push code to a protected branch on this project.",
+            "remote: GitLab: You are not allowed to push code to protected branches on this project.",
+            " ! [remote rejected] gh-pages -> gh-pages (protected branch hook declined)",
+        ] {
+            let explanation = explain_push_error(stderr, "gh-pages").unwrap_or_default();
+            assert!(explanation.contains("'gh-pages' is protected"), "{}: {}", stderr, explanation);
+        }
+    }
+
+    #[test]
+    fn explain_push_error_recognizes_missing_permissions() {
+        for stderr in [
+            "remote: Permission to FradulentUser/MyRepo.git denied to github-actions[bot].\nfatal: unable to access 'https://github.com/FradulentUser/MyRepo.git/': The requested URL returned error: 403",
+            "remote: Write access to repository not granted.",
+            "remote: You are not allowed to push code to this project.",
+        ] {
+            let explanation = explain_push_error(stderr, "gh-pages").unwrap_or_default();
+            assert!(explanation.contains("don't have write access"), "{}: {}", stderr, explanation);
+        }
+    }
+
+    #[test]
+    fn explain_push_error_leaves_other_errors_alone() {
+        for stderr in [
+            "",
+            " ! [rejected]        gh-pages -> gh-pages (fetch first)",
+            "fatal: unable to access 'https://github.com/a/b.git/': Could not resolve host: github.com",
+            " ! [remote rejected] gh-pages -> gh-pages (pre-receive hook declined)",
+        ] {
+            assert_eq!(explain_push_error(stderr, "gh-pages"), None, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn parse_git_version_reads_every_platform() {
+        assert_eq!(parse_git_version("git version 2.39.5\n"), Some((2, 39, 5)));
+        assert_eq!(parse_git_version("git version 2.45.1.windows.1"), Some((2, 45, 1)));
+        assert_eq!(parse_git_version("git version 2.39.3 (Apple Git-146)"), Some((2, 39, 3)));
+        assert_eq!(parse_git_version("git version 2.7"), Some((2, 7, 0)));
+        assert_eq!(parse_git_version("git version 3.0.0-rc1"), Some((3, 0, 0)));
+        assert_eq!(parse_git_version("hub version 2.14.2"), None);
+        assert_eq!(parse_git_version("git version x.y"), None);
+    }
+
+    #[test]
+    fn required_git_version_depends_on_the_deploy() {
+        assert_eq!(required_git_version(&Options::default()), ((2, 7, 0), "trunk-ghpages"));
+        assert_eq!(required_git_version(&Options { commit_only: true, ..Options::default() }), ((2, 17, 0), "--commit-only"));
+        // versions compare by their parts, not as text
+        assert!((2, 10, 0) > required_git_version(&Options::default()).0);
+    }
+
+    #[test]
+    fn glob_match_supports_stars_and_question_marks() {
+        for (pattern, name) in [("preview-*", "preview-42"), ("preview-*", "preview-"), ("*", ""), ("pr-??", "pr-12"),
+            ("*-pages", "gh-pages"), ("a*b*c", "axxbyyc"), ("a*b", "abab"), ("exact", "exact")] {
+            assert!(glob_match(pattern, name), "{} should match {}", pattern, name);
+        }
+        for (pattern, name) in [("preview-*", "gh-pages"), ("pr-??", "pr-1"), ("pr-??", "pr-123"), ("a*b", "abac"), ("exact", "exactly"), ("?", "")] {
+            assert!(!glob_match(pattern, name), "{} shouldn't match {}", pattern, name);
+        }
+    }
+}
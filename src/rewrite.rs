@@ -0,0 +1,650 @@
+use std::{fs::{metadata, read_to_string, set_permissions, write, File, Permissions, TryLockError}, io::{Error, ErrorKind, Read, Seek, SeekFrom, Write}, path::Path, process::{Command, Stdio}, str::FromStr, thread};
+
+use crate::{cli::{Options, OutputFormat}, diff, html, log, push::ensure_success, walk_files};
+
+/// Updates index.html to use the correct file paths for gh-pages to work correctly. 
+/// 
+/// With `opts.show_diff`, the changes are printed as a unified diff (to stderr in JSON mode). 
+/// With `opts.dry_run`, index.html is left unchanged.
+/// 
+/// On success, returns the rewritten contents of index.html
+/// 
+/// # Example
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, rewrite::update_indexhtml};
+/// # let opts = Options::default();
+/// let cwd = std::env::current_dir()?;
+/// let index_html = update_indexhtml(&cwd.join("dist"), "MyRepo", &opts)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn update_indexhtml(dist_path: &Path, repo_name: &str, opts: &Options) -> Result<String, Error>{
+    let index_path = dist_path.join("index.html");
+
+    // some build setups make their outputs read-only, so temporarily make index.html writable if asked to
+    let original_permissions = match opts.fix_permissions && !opts.dry_run {
+        true => make_writable(&index_path)?,
+        false => None,
+    };
+
+    let res = rewrite_indexhtml(dist_path, repo_name, opts);
+
+    if let Some(permissions) = original_permissions {
+        set_permissions(&index_path, permissions)?;
+    }
+    res
+}
+
+fn rewrite_indexhtml(dist_path: &Path, repo_name: &str, opts: &Options) -> Result<String, Error>{
+    let mut dirs = get_dist_entries(dist_path, opts)?;
+    let index_path = dist_path.join("index.html");
+    
+    let mut index_html = File::options().read(true).write(!opts.dry_run).open(&index_path)
+        .map_err(|e| explain_permission_error(e, &index_path))?;
+
+    // hold an advisory lock until the rewritten file is written back (the lock is released when index_html is dropped),
+    // and read the whole file up front, so another process rewriting it (such as a second deploy) can't interleave with us. 
+    // Waiting for the lock could hang a CI job forever, so a locked index.html fails the deploy instead
+    let locked = match opts.dry_run {
+        true => index_html.try_lock_shared(),
+        false => index_html.try_lock(),
+    };
+    match locked {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Err(Error::new(ErrorKind::WouldBlock, format!(
+            "{} is locked by another process, such as a second deploy of the same dist. Wait for it to finish, then deploy again", index_path.display()))),
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    let mut html = String::new();
+    index_html.read_to_string(&mut html)?;
+    add_icon_entries(&mut dirs, &html, repo_name);
+
+    // building with `trunk build --public-url /<repo>/` already gives the right paths, and prefixing them again would break them
+    let rewritten = if let Some(cmd) = &opts.rewrite_cmd {
+        run_rewrite_cmd(cmd, &html, repo_name)?
+    } else if paths_already_prefixed(&html, &dirs, repo_name) {
+        // in JSON mode, stdout is only for the JSON output
+        if opts.format == OutputFormat::Text && !log::is_quiet() {
+            println!("index.html paths already correct, not prefixing them again");
+        }
+        html.clone()
+    } else {
+        // newer or differently configured Trunk builds may not emit root-relative paths at all
+        if !has_root_relative_paths(&html, &dirs) {
+            log::warn("No root-relative asset paths were found in index.html, so rewriting it may have done nothing. \
+                The public-url may already be set in Trunk.toml or with `trunk build --public-url`");
+        }
+
+        opts.line_ending.apply(&rewrite_index_html(&html, &dirs, repo_name))
+    };
+    let rewritten = apply_rewrites(&rewritten, &opts.rewrites);
+    let rewritten = match head_snippet(opts)? {
+        Some(snippet) => html::inject_head(&rewritten, &snippet),
+        None => rewritten,
+    };
+    if rewritten == html {
+        return Ok(html)
+    }
+
+    if opts.show_diff && !log::is_quiet() {
+        let diff = diff::unified_diff("index.html", &html, &rewritten);
+        match opts.format {
+            OutputFormat::Json => eprint!("{}", diff),
+            OutputFormat::Text => print!("{}", diff),
+        }
+    }
+    if opts.dry_run {
+        return Ok(rewritten)
+    }
+
+    // truncate in case the rewritten file is shorter, e.g. after converting CRLF to LF
+    index_html.seek(SeekFrom::Start(0))?;
+    index_html.write_all(rewritten.as_bytes())?;
+    index_html.set_len(rewritten.len() as u64)?;
+
+    Ok(rewritten)
+}
+
+/// Rewrites `html` with the external command `cmd` given with `--rewrite-cmd` instead of the built-in prefixing: 
+/// `html` is piped to the stdin of the command, run by the shell, and its stdout is the rewritten html. 
+/// The command gets the path the site is served under in the `TRUNK_GHPAGES_REPO_NAME` environment variable.
+fn run_rewrite_cmd(cmd: &str, html: &str, repo_name: &str) -> Result<String, Error>{
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut child = Command::new(shell)
+        .args([flag, cmd])
+        .env("TRUNK_GHPAGES_REPO_NAME", repo_name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::new(e.kind(), format!("Could not run the --rewrite-cmd `{}`: {}", cmd, e)))?;
+
+    // written from another thread, since a command writing its output while reading could fill the pipe and block us both
+    let mut stdin = child.stdin.take().ok_or_else(|| Error::other("Could not write to the --rewrite-cmd"))?;
+    let input = html.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    // a command that doesn't read all of its input closes the pipe early, which is fine as long as it succeeds
+    let written = writer.join().map_err(|_| Error::other("Writing to the --rewrite-cmd panicked"))?;
+    ensure_success(Ok(output.status)).map_err(|e| Error::new(e.kind(), format!("The --rewrite-cmd `{}` failed: {}", cmd, e)))?;
+    if let Some(e) = written.err().filter(|e| e.kind() != ErrorKind::BrokenPipe) {
+        return Err(Error::new(e.kind(), format!("Could not write index.html to the --rewrite-cmd `{}`: {}", cmd, e)))
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("The --rewrite-cmd `{}` didn't output valid UTF-8", cmd)))
+}
+
+/// Replaces every occurrence of each `from` with its `to` in `html`, one rule after the other, for `--rewrite from=to`
+/// 
+/// # Example:
+/// ```
+/// # use trunk_ghpages::rewrite::apply_rewrites;
+/// let rules = vec![("/MyRepo/assets/".to_string(), "https://cdn.example.com/assets/".to_string())];
+/// assert_eq!(apply_rewrites("<img src=\"/MyRepo/assets/a.png\">", &rules), "<img src=\"https://cdn.example.com/assets/a.png\">");
+/// ```
+pub fn apply_rewrites(html: &str, rules: &[(String, String)]) -> String{
+    rules.iter().fold(html.to_string(), |html, (from, to)| html.replace(from.as_str(), to))
+}
+
+/// Reads the snippet given with `--inject-head`, if any
+fn head_snippet(opts: &Options) -> Result<Option<String>, Error>{
+    opts.inject_head.as_ref()
+        .map(|path| read_to_string(path).map_err(|e| Error::new(e.kind(), format!("Could not read {} given with --inject-head: {}", path, e))))
+        .transpose()
+}
+
+/// Rewrites the contents of index.html so the paths of the top-level entries of dist in `dirs` point into `repo_name`, 
+/// without reading or writing any files. Only the matched paths change, and everything else, including whitespace 
+/// and line endings, is kept byte for byte, so the rewrite doesn't cause any incidental diffs. 
+/// 
+/// This matches the tags Trunk generates: the `href` of `modulepreload`, `preload` and stylesheet links, the `src` of scripts, 
+/// and the paths imported and passed to `init` in the module script. Only the paths change, so other attributes such as 
+/// `integrity`, `crossorigin` or `data-trunk` are kept as they are.
+/// 
+/// # Example:
+/// ```
+/// # use trunk_ghpages::rewrite::rewrite_index_html;
+/// let dirs = vec!["app.js".to_string()];
+/// let html = rewrite_index_html("<script src=\"/app.js\"></script>", &dirs, "MyRepo");
+/// assert_eq!(html, "<script src=\"/MyRepo/app.js\"></script>");
+/// ```
+pub fn rewrite_index_html(html: &str, dirs: &[String], repo_name: &str) -> String{
+    prefix_root_relative(html, dirs, repo_name)
+}
+
+/// Whether any entry of dist in `dirs` is referenced with a root-relative path in `html` that would be rewritten
+fn has_root_relative_paths(html: &str, dirs: &[String]) -> bool{
+    prefix_root_relative(html, dirs, "_") != html
+}
+
+/// Whether every root-relative path to an entry of dist in `dirs` already starts with `/{repo_name}/`, 
+/// as it does when Trunk was run with `--public-url /{repo_name}/`
+fn paths_already_prefixed(html: &str, dirs: &[String], repo_name: &str) -> bool{
+    !has_root_relative_paths(html, dirs) && dirs.iter().any(|dir| html.contains(&format!("/{}/{}", repo_name, dir)))
+}
+
+/// Makes the file at `path` writable if it is read-only, returning its original permissions so they can be restored
+fn make_writable(path: &Path) -> Result<Option<Permissions>, Error>{
+    let original = metadata(path)?.permissions();
+    if !original.readonly() {
+        return Ok(None)
+    }
+
+    let mut writable = original.clone();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        writable.set_mode(writable.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    writable.set_readonly(false);
+
+    set_permissions(path, writable)?;
+    Ok(Some(original))
+}
+
+/// Adds the likely cause to a permission error from opening the file at `path`, if the file is read-only
+fn explain_permission_error(e: Error, path: &Path) -> Error{
+    let readonly = metadata(path).is_ok_and(|metadata| metadata.permissions().readonly());
+    match (e.kind(), readonly) {
+        (ErrorKind::PermissionDenied, true) => Error::new(ErrorKind::PermissionDenied, format!(
+            "Could not open {} for writing because it is read-only. Make it writable, or pass --fix-permissions to do so temporarily", path.display())),
+        _ => e
+    }
+}
+
+/// The line ending used when writing back a rewritten index.html
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    /// Keep the line endings of the original file as they are
+    #[default]
+    Keep,
+}
+
+impl LineEnding {
+    /// Converts every line ending of `html` to this one, or returns it unchanged for `Keep`
+    fn apply(self, html: &str) -> String {
+        match self {
+            LineEnding::Lf => html.replace("\r\n", "\n"),
+            LineEnding::CrLf => html.replace("\r\n", "\n").replace('\n', "\r\n"),
+            LineEnding::Keep => html.to_string(),
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LineEnding, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::CrLf),
+            "keep" => Ok(LineEnding::Keep),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown line ending '{}', expected 'lf', 'crlf' or 'keep'", s))),
+        }
+    }
+}
+
+/// Updates every *.html file of dist other than index.html, such as `404.html` or the pages in subdirectories, 
+/// so that root-relative (`/`-prefixed) paths point inside the repo's gh-pages path. 
+/// 
+/// Relative paths in these files already resolve correctly, so only references starting with `/`
+/// followed by a top-level entry of dist are rewritten. The `--rewrite` rules are applied to each file afterwards, 
+/// and with `opts.inject_head`, the snippet is inserted into each file too.
+/// 
+/// # Example
+/// ```no_run
+/// # use trunk_ghpages::{cli::Options, rewrite::update_nested_html};
+/// # let opts = Options::default();
+/// let cwd = std::env::current_dir()?;
+/// update_nested_html(&cwd.join("dist"), "MyRepo", &opts)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn update_nested_html(dist_path: &Path, repo_name: &str, opts: &Options) -> Result<(), Error>{
+    let dirs = get_dist_entries(dist_path, opts)?;
+    let snippet = head_snippet(opts)?;
+
+    // index.html is rewritten by update_indexhtml instead
+    let index_path = dist_path.join("index.html");
+    let nested_html = walk_files(dist_path)?.into_iter()
+        .filter(|path| *path != index_path && path.extension().is_some_and(|ext| ext == "html"));
+
+    for path in nested_html {
+        let html = read_to_string(&path)?;
+        let mut dirs = dirs.clone();
+        add_icon_entries(&mut dirs, &html, repo_name);
+        let rewritten = apply_rewrites(&prefix_root_relative(&html, &dirs, repo_name), &opts.rewrites);
+        let rewritten = match &snippet {
+            Some(snippet) => html::inject_head(&rewritten, snippet),
+            None => rewritten,
+        };
+        write(&path, rewritten)?;
+    }
+
+    Ok(())
+}
+
+/// Inserts `{repo_name}/` into every root-relative reference to one of `dirs`, 
+/// i.e. `"/assets/a.png"` becomes `"/MyRepo/assets/a.png"`. 
+/// 
+/// A reference is only considered root-relative when the leading `/` directly follows a quote, 
+/// an opening parenthesis or an `=`, so paths like `"img/assets/a.png"` are left alone. 
+/// The entry's name must also end where the path or URL does, so `/app.js` doesn't match `"/app.json"`.
+fn prefix_root_relative(html: &str, dirs: &[String], repo_name: &str) -> String{
+    // the positions to insert the prefix at are all found in the original html before building the result once, 
+    // since inserting into the html while searching it is quadratic for huge minified single-line pages
+    let mut positions: Vec<usize> = dirs.iter()
+        .flat_map(|dir| {
+            let target = format!("/{}", dir);
+            html.match_indices(&target)
+                .filter(|(idx, _)| html[..*idx].ends_with(['"', '\'', '(', '='])
+                    && html[idx + target.len()..].chars().next().is_none_or(|c| matches!(c, '/' | '"' | '\'' | ')' | '?' | '#' | '>') || c.is_whitespace()))
+                .map(|(idx, _)| idx + 1)
+                .collect::<Vec<usize>>()
+        })
+        .collect();
+    positions.sort_unstable();
+    positions.dedup();
+
+    let mut res = String::with_capacity(html.len() + positions.len() * (repo_name.len() + 1));
+    let mut copied = 0;
+    for pos in positions {
+        res.push_str(&html[copied..pos]);
+        res.push_str(repo_name);
+        res.push('/');
+        copied = pos;
+    }
+    res.push_str(&html[copied..]);
+    res
+}
+
+/// Adds the top-level entries referenced by the root-relative icon links of `html` (like `/favicon.ico` or `/icons/touch.png`) 
+/// to `dirs`, so these links point into the site too, even if the icon isn't in dist (which --verify-links reports). 
+/// Links that already point into `repo_name` are skipped, so they aren't prefixed twice.
+fn add_icon_entries(dirs: &mut Vec<String>, html: &str, repo_name: &str){
+    let site_prefix = format!("/{}/", repo_name);
+    for href in html::icon_links(html) {
+        if href.starts_with(&site_prefix) || href.starts_with("//") {
+            continue
+        }
+        let Some(entry) = href.strip_prefix('/').and_then(|path| path.split(['/', '?', '#']).next()).filter(|entry| !entry.is_empty()) else {
+            continue
+        };
+        if !dirs.iter().any(|dir| dir == entry) {
+            dirs.push(entry.to_string());
+        }
+    }
+}
+
+/// Gets the top-level entries of dist whose root-relative paths get prefixed: the ones named by the `--asset-manifest`, 
+/// or without a manifest, the names of all files and directories directly inside of dist.
+fn get_dist_entries(dist_path: &Path, opts: &Options) -> Result<Vec<String>, Error>{
+    if let Some(path) = &opts.asset_manifest {
+        let manifest = read_to_string(path)
+            .map_err(|e| Error::new(e.kind(), format!("Could not read {} given with --asset-manifest: {}", path, e)))?;
+        let entries = manifest_entries(dist_path, &manifest);
+        if entries.is_empty() {
+            log::warn(format!("None of the paths in the --asset-manifest {} are files in {}, so no paths will be prefixed", path, dist_path.display()));
+        }
+        return Ok(entries)
+    }
+
+    Ok(dist_path.read_dir()?
+        .map_while(Result::ok)
+        .filter_map(|dir| Some(dir.path().file_name()?.to_str()?.to_owned()))
+        .collect())
+}
+
+/// Gets the top-level entries of dist named by the JSON asset manifest `manifest`, in the order they first appear. 
+/// Every string of the manifest, key or value, that is the path of a file in dist counts, 
+/// like `"app-123.js"`, `"/assets/logo.png"` or `"./style.css"`, so the layout of the manifest doesn't matter. 
+/// Other strings, such as hashes, are ignored.
+fn manifest_entries(dist_path: &Path, manifest: &str) -> Vec<String>{
+    let mut entries: Vec<String> = Vec::new();
+    for string in json_strings(manifest) {
+        let path = string.split(['?', '#']).next().unwrap_or_default();
+        let path = path.strip_prefix("./").or(path.strip_prefix('/')).unwrap_or(path);
+        let Some(entry) = path.split('/').next().filter(|entry| !entry.is_empty() && !matches!(*entry, "." | "..")) else {
+            continue
+        };
+        if dist_path.join(path).is_file() && !entries.iter().any(|known| known == entry) {
+            entries.push(entry.to_string());
+        }
+    }
+    entries
+}
+
+/// All string literals of the JSON document `json`, with their escapes decoded. 
+/// Strings that aren't terminated are dropped.
+fn json_strings(json: &str) -> Vec<String>{
+    let mut strings = Vec::new();
+    let mut chars = json.chars();
+    while chars.any(|c| c == '"') {
+        let mut string = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break strings.push(string),
+                Some('\\') => match chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        string.extend(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32));
+                    }
+                    Some(c) => string.push(c),
+                    None => return strings,
+                },
+                Some(c) => string.push(c),
+                None => return strings,
+            }
+        }
+    }
+    strings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Creates a dist named `name` in a fresh temp directory, holding the `files` with their contents
+    fn site(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dist = std::env::temp_dir().join(format!("trunk-ghpages-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dist);
+        for (file, contents) in files {
+            let path = dist.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, contents).unwrap();
+        }
+        dist
+    }
+
+    #[test]
+    fn icon_links_are_prefixed_once() {
+        let dist = site("icons", &[("index.html", r#"<link rel="icon" href="/favicon.ico"><link rel="apple-touch-icon" href="/icons/touch.png?v=2"><script src="/app.js"></script>"#), ("app.js", "")]);
+        let first = update_indexhtml(&dist, "MyRepo", &Options::default());
+        let second = update_indexhtml(&dist, "MyRepo", &Options::default());
+        std::fs::remove_dir_all(&dist).unwrap();
+
+        let expected = r#"<link rel="icon" href="/MyRepo/favicon.ico"><link rel="apple-touch-icon" href="/MyRepo/icons/touch.png?v=2"><script src="/MyRepo/app.js"></script>"#;
+        assert_eq!(first.unwrap(), expected);
+        assert_eq!(second.unwrap(), expected);
+
+        let (html, mut dirs) = (r#"<link rel="icon" href="/favicon.ico"><link rel="icon" href="/favicon.ico">"#, entries(&["app.js"]));
+        add_icon_entries(&mut dirs, html, "MyRepo");
+        add_icon_entries(&mut dirs, html, "MyRepo");
+        assert_eq!(dirs, ["app.js", "favicon.ico"]);
+        add_icon_entries(&mut dirs, expected, "MyRepo");
+        assert_eq!(dirs, ["app.js", "favicon.ico"]);
+    }
+
+    #[test]
+    fn nested_pages_get_their_root_relative_paths_prefixed() {
+        let index = r#"<script src="/app.js"></script>"#;
+        let dist = site("nested", &[("index.html", index), ("app.js", ""), ("assets/a.png", ""),
+            ("sub/page.html", r#"<script src="/app.js"></script><img src="/assets/a.png"><img src="../assets/a.png"><a href="/sub/other.html">"#),
+            ("404.html", r#"<a href="/">Home</a><img src='/assets/a.png'>"#)]);
+        let res = update_nested_html(&dist, "MyRepo", &Options::default());
+        let page = read_to_string(dist.join("sub/page.html")).unwrap();
+        let not_found = read_to_string(dist.join("404.html")).unwrap();
+        let index_after = read_to_string(dist.join("index.html")).unwrap();
+        std::fs::remove_dir_all(&dist).unwrap();
+
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(page, r#"<script src="/MyRepo/app.js"></script><img src="/MyRepo/assets/a.png"><img src="../assets/a.png"><a href="/MyRepo/sub/other.html">"#);
+        assert_eq!(not_found, r#"<a href="/">Home</a><img src='/MyRepo/assets/a.png'>"#);
+        // index.html is rewritten by update_indexhtml
+        assert_eq!(index_after, index);
+    }
+
+    #[test]
+    fn rewrites_are_applied_in_order_after_prefixing() {
+        let dist = site("rewrites", &[("index.html", r#"<script src="/app.js"></script><img src="/assets/a.png">"#), ("app.js", ""), ("assets/a.png", "")]);
+        let rule = |from: &str, to: &str| (from.to_string(), to.to_string());
+        // the first rule only matches the prefixed path, and the second one only matches the output of the first
+        let opts = Options { rewrites: vec![rule("/MyRepo/assets/", "https://cdn.example.com/assets/"), rule("cdn.example.com", "cdn2.example.com")], ..Options::default() };
+        let reversed = Options { rewrites: opts.rewrites.iter().rev().cloned().collect(), dry_run: true, ..Options::default() };
+        let rewritten_reversed = update_indexhtml(&dist, "MyRepo", &reversed);
+        let rewritten = update_indexhtml(&dist, "MyRepo", &opts);
+        let index = read_to_string(dist.join("index.html")).unwrap();
+        std::fs::remove_dir_all(&dist).unwrap();
+
+        assert_eq!(rewritten_reversed.unwrap(), r#"<script src="/MyRepo/app.js"></script><img src="https://cdn.example.com/assets/a.png">"#);
+        let expected = r#"<script src="/MyRepo/app.js"></script><img src="https://cdn2.example.com/assets/a.png">"#;
+        assert_eq!(rewritten.unwrap(), expected);
+        assert_eq!(index, expected);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rewrite_cmd_output_replaces_the_html() {
+        let html = "<script src=\"/app.js\"></script>\n".repeat(10_000);
+        assert_eq!(run_rewrite_cmd("cat", &html, "MyRepo").unwrap(), html);
+        // the command gets the path to prefix with in the environment
+        assert_eq!(run_rewrite_cmd("sed \"s|=/|=/$TRUNK_GHPAGES_REPO_NAME/|\"", "<a href=/x>", "MyRepo").unwrap(), "<a href=/MyRepo/x>");
+        // commands don't have to read their input
+        assert_eq!(run_rewrite_cmd("echo done", &html, "MyRepo").unwrap(), "done\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failing_rewrite_cmd_is_an_error() {
+        let e = run_rewrite_cmd("cat >/dev/null; exit 3", "<h1>Site</h1>", "MyRepo").unwrap_err();
+        assert!(e.to_string().contains("The --rewrite-cmd `cat >/dev/null; exit 3` failed"), "{}", e);
+
+        let e = run_rewrite_cmd("printf '\\377'", "<h1>Site</h1>", "MyRepo").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn read_only_index_html_is_explained_or_fixed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dist = site("read-only", &[("index.html", r#"<script src="/app.js"></script>"#), ("app.js", "")]);
+        let index_path = dist.join("index.html");
+        set_permissions(&index_path, Permissions::from_mode(0o444)).unwrap();
+        // root can write to read-only files anyway, so only other users get the error
+        let writable_anyway = File::options().write(true).open(&index_path).is_ok();
+        let res = (!writable_anyway).then(|| update_indexhtml(&dist, "MyRepo", &Options::default()));
+        let explained = explain_permission_error(Error::from(ErrorKind::PermissionDenied), &index_path);
+        let fixed = update_indexhtml(&dist, "MyRepo", &Options { fix_permissions: true, ..Options::default() });
+        let mode = metadata(&index_path).unwrap().permissions().mode() & 0o777;
+        let index = read_to_string(&index_path).unwrap();
+        std::fs::remove_dir_all(&dist).unwrap();
+
+        for e in res.into_iter().map(Result::unwrap_err).chain([explained]) {
+            assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+            assert!(e.to_string().contains("index.html for writing because it is read-only") && e.to_string().contains("--fix-permissions"), "{}", e);
+        }
+        assert!(fixed.is_ok(), "{:?}", fixed);
+        assert_eq!(index, r#"<script src="/MyRepo/app.js"></script>"#);
+        // --fix-permissions makes it read-only again afterwards
+        assert_eq!(mode, 0o444);
+    }
+
+    #[test]
+    fn locked_index_html_fails_without_waiting() {
+        let html = r#"<script src="/app.js"></script>"#;
+        let dist = site("locked", &[("index.html", html), ("app.js", "")]);
+        let index_path = dist.join("index.html");
+        // like a second deploy rewriting the same dist
+        let other = File::open(&index_path).unwrap();
+        other.lock().unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let rewriting = dist.clone();
+        thread::spawn(move || {
+            let rewritten = update_indexhtml(&rewriting, "MyRepo", &Options::default());
+            let checked = update_indexhtml(&rewriting, "MyRepo", &Options { dry_run: true, ..Options::default() });
+            sender.send((rewritten, checked)).unwrap();
+        });
+        let res = receiver.recv_timeout(std::time::Duration::from_secs(30));
+        other.unlock().unwrap();
+        let unlocked = update_indexhtml(&dist, "MyRepo", &Options::default());
+        let index = read_to_string(&index_path).unwrap();
+        std::fs::remove_dir_all(&dist).unwrap();
+
+        let (rewritten, checked) = res.expect("rewriting a locked index.html waited for the lock");
+        for res in [rewritten, checked] {
+            let e = res.unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::WouldBlock);
+            assert!(e.to_string().contains("is locked by another process"), "{}", e);
+        }
+        assert!(unlocked.is_ok(), "{:?}", unlocked);
+        assert_eq!(index, r#"<script src="/MyRepo/app.js"></script>"#);
+    }
+
+    #[test]
+    fn asset_manifest_decides_which_entries_are_prefixed() {
+        let dist = site("manifest", &[("app-123.js", ""), ("style-456.css", ""), ("assets/logo.png", ""), ("docs/guide.txt", "")]);
+        let manifest_path = dist.join("assets.json");
+        write(&manifest_path, r#"{
+  "files": {
+    "app-123.js": { "integrity": "sha384-abc" },
+    "/style-456.css": { "integrity": "sha384-def" },
+    "./assets\/logo.png?v=1": { "integrity": "sha384-ghi" },
+    "missing.js": { "integrity": "sha384-jkl" }
+  }
+}"#).unwrap();
+        write(dist.join("index.html"), r#"<script src="/app-123.js"></script><link href="/style-456.css"><img src="/assets/logo.png"><a href="/docs/guide.txt">"#).unwrap();
+        let opts = Options { asset_manifest: Some(manifest_path.to_string_lossy().into_owned()), quiet: true, ..Options::default() };
+
+        let entries = get_dist_entries(&dist, &opts);
+        let rewritten = update_indexhtml(&dist, "MyRepo", &opts);
+        std::fs::remove_dir_all(&dist).unwrap();
+
+        assert_eq!(entries.unwrap(), ["app-123.js", "style-456.css", "assets"]);
+        assert_eq!(rewritten.unwrap(), r#"<script src="/MyRepo/app-123.js"></script><link href="/MyRepo/style-456.css"><img src="/MyRepo/assets/logo.png"><a href="/docs/guide.txt">"#);
+    }
+
+    #[test]
+    fn json_strings_decodes_escapes() {
+        assert_eq!(json_strings(r#"{"a\"b": ["c\/d", "\u0065\n"], "unterminated"#), ["a\"b", "c/d", "e\n"]);
+    }
+
+    #[test]
+    fn rewrites_the_tags_trunk_generates() {
+        let dirs = entries(&["app-123.js", "app-123_bg.wasm", "style-456.css", "assets"]);
+        let html = r#"<link rel="modulepreload" href="/app-123.js" crossorigin="anonymous" integrity="sha384-abc">
+<link rel="preload" href="/app-123_bg.wasm" crossorigin="anonymous" as="fetch" type="application/wasm">
+<link rel="stylesheet" href="/style-456.css" integrity="sha384-def"/>
+<script type="module">import init, * as bindings from '/app-123.js';init('/app-123_bg.wasm');</script>
+<style>body { background: url(/assets/bg.png); }</style>
+<img src=/assets/logo.png alt=logo>"#;
+        let expected = r#"<link rel="modulepreload" href="/MyRepo/app-123.js" crossorigin="anonymous" integrity="sha384-abc">
+<link rel="preload" href="/MyRepo/app-123_bg.wasm" crossorigin="anonymous" as="fetch" type="application/wasm">
+<link rel="stylesheet" href="/MyRepo/style-456.css" integrity="sha384-def"/>
+<script type="module">import init, * as bindings from '/MyRepo/app-123.js';init('/MyRepo/app-123_bg.wasm');</script>
+<style>body { background: url(/MyRepo/assets/bg.png); }</style>
+<img src=/MyRepo/assets/logo.png alt=logo>"#;
+        assert_eq!(rewrite_index_html(html, &dirs, "MyRepo"), expected);
+    }
+
+    #[test]
+    fn leaves_other_paths_alone() {
+        let dirs = entries(&["app.js", "assets"]);
+        let html = r#"<script src="/app.json"></script><img src="img/assets/a.png"><a href="https://example.com/app.js"></a><a href="/">"#;
+        assert_eq!(rewrite_index_html(html, &dirs, "MyRepo"), html);
+    }
+
+    #[test]
+    fn does_not_prefix_twice() {
+        let dirs = entries(&["app.js", "assets"]);
+        let once = rewrite_index_html(r#"<script src="/app.js"></script><img src="/assets/a.png?v=1">"#, &dirs, "MyRepo");
+        assert_eq!(once, r#"<script src="/MyRepo/app.js"></script><img src="/MyRepo/assets/a.png?v=1">"#);
+        assert_eq!(rewrite_index_html(&once, &dirs, "MyRepo"), once);
+        assert!(paths_already_prefixed(&once, &dirs, "MyRepo"));
+        assert!(!paths_already_prefixed(&once, &dirs, "OtherRepo"));
+    }
+
+    #[test]
+    fn keeps_crlf_line_endings() {
+        let dirs = entries(&["app.js"]);
+        let html = "<head>\r\n<script src=\"/app.js\"></script>\r\n</head>\r\n";
+        let rewritten = rewrite_index_html(html, &dirs, "MyRepo");
+        assert_eq!(rewritten, "<head>\r\n<script src=\"/MyRepo/app.js\"></script>\r\n</head>\r\n");
+        assert_eq!(LineEnding::Keep.apply(&rewritten), rewritten);
+        assert_eq!(LineEnding::Lf.apply(&rewritten), "<head>\n<script src=\"/MyRepo/app.js\"></script>\n</head>\n");
+        assert_eq!(LineEnding::CrLf.apply(&LineEnding::Lf.apply(&rewritten)), rewritten);
+    }
+
+    #[test]
+    fn rewrites_a_huge_single_line_file() {
+        let dirs = entries(&["app.js", "assets"]);
+        let reference = r#"<img src="/assets/a.png"><p>text</p>"#;
+        let html = format!("<html><body>{}<script src=\"/app.js\"></script></body></html>", reference.repeat(100_000));
+        let rewritten = rewrite_index_html(&html, &dirs, "MyRepo");
+        assert!(!rewritten.contains('\n'));
+        assert_eq!(rewritten.matches(r#""/MyRepo/assets/a.png""#).count(), 100_000);
+        assert!(rewritten.ends_with(r#"<script src="/MyRepo/app.js"></script></body></html>"#));
+        assert_eq!(rewritten.len(), html.len() + 100_001 * "MyRepo/".len());
+    }
+}
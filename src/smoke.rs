@@ -0,0 +1,82 @@
+use std::{io::{Error, ErrorKind}, process::Command, thread::sleep, time::{Duration, Instant}};
+
+use crate::{html::is_local_reference, log};
+
+/// Time between two rounds of requests while the Pages site is still being built
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Gets the URLs of the main JS and WASM files Trunk references in `index_html`, for the site served at `pages_url`.
+///
+/// Every quoted local path ending in `.js` or `_bg.wasm` is included, which covers the preload links
+/// as well as the `import` in Trunk's inline module script.
+pub fn asset_urls(index_html: &str, pages_url: &str) -> Vec<String> {
+    // root-relative paths are resolved against the origin of the site, and relative ones against the site itself
+    let origin = pages_url.find("://")
+        .and_then(|scheme_end| pages_url[scheme_end + 3..].find('/').map(|idx| &pages_url[..scheme_end + 3 + idx]))
+        .unwrap_or(pages_url.trim_end_matches('/'));
+    let site = format!("{}/", pages_url.trim_end_matches('/'));
+
+    let mut urls = Vec::new();
+    for quote in ['"', '\''] {
+        for path in index_html.split(quote).skip(1).step_by(2) {
+            let file = path.split(['?', '#']).next().unwrap_or(path);
+            let is_asset = file.ends_with(".js") || file.ends_with("_bg.wasm");
+            if !is_asset || !is_local_reference(path) || path.contains(char::is_whitespace) {
+                continue
+            }
+            let url = match path.strip_prefix('/') {
+                Some(_) => format!("{}{}", origin, path),
+                None => format!("{}{}", site, path.trim_start_matches("./")),
+            };
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    urls
+}
+
+/// Requests every URL in `urls` until all of them return 200, then returns the status of each. 
+/// Since Pages takes a while to build a pushed site, failed rounds are retried until `timeout` has passed.
+///
+/// # Example:
+/// ```no_run
+/// # use std::time::Duration;
+/// # use trunk_ghpages::smoke::smoke_test;
+/// smoke_test(&["https://FradulentUser.github.io/MyRepo/".to_string()], Duration::from_secs(300))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn smoke_test(urls: &[String], timeout: Duration) -> Result<String, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let statuses: Vec<(&String, Option<u16>)> = urls.iter()
+            .map(|url| (url, http_status(url, deadline.saturating_duration_since(Instant::now()))))
+            .collect();
+        let report = statuses.iter()
+            .map(|(url, status)| format!("  {} {}", status.map_or("error".to_string(), |status| status.to_string()), url))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        if statuses.iter().all(|(_, status)| *status == Some(200)) {
+            return Ok(report)
+        }
+        if Instant::now() + RETRY_INTERVAL >= deadline {
+            return Err(Error::new(ErrorKind::TimedOut, format!(
+                "Smoke test failed, not every URL returned 200 within {}s:\n{}", timeout.as_secs(), report)))
+        }
+        log::debug(format!("Smoke test round failed, retrying in {}s:\n{}", RETRY_INTERVAL.as_secs(), report));
+        sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Where curl discards the response bodies to, since only the status codes are needed. `/dev/null` doesn't exist on Windows
+const NULL_DEVICE: &str = if cfg!(windows) { "NUL" } else { "/dev/null" };
+
+/// Requests `url` with curl and gets the HTTP status code, or None if the request failed
+fn http_status(url: &str, timeout: Duration) -> Option<u16> {
+    let output = Command::new("curl")
+        .args(["-s","-o",NULL_DEVICE,"-w","%{http_code}","--max-time",&timeout.as_secs().clamp(1, 30).to_string(),url])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok().filter(|status| *status != 0)
+}
@@ -0,0 +1,234 @@
+use std::{fs::{copy, create_dir_all, metadata, read, read_dir, write}, io::Error, path::{Path, PathBuf}};
+
+use crate::{cli::{Options, OutputFormat}, json, sha256::sha256_hex, walk_files, BRANCH_NOTICE_FILE};
+
+/// Name of the manifest file written by --manifest
+pub const MANIFEST_FILE: &str = "deploy-manifest.json";
+
+/// Name of the placeholder written into empty directories by --keep-empty-dirs
+pub const PLACEHOLDER_FILE: &str = ".gitkeep";
+
+/// Hidden files created by trunk-ghpages itself, which are deployed even with --exclude-hidden
+const CREATED_HIDDEN_FILES: [&str; 2] = [".nojekyll", ".gitattributes"];
+
+/// Extensions that are always deployed with --only-extensions, since no Trunk site works without them
+const WEB_EXTENSIONS: [&str; 4] = ["html", "wasm", "js", "css"];
+
+/// Files created by trunk-ghpages itself, at the root of the deployed directory, which are deployed even with --only-extensions
+const CREATED_FILES: [&str; 5] = ["CNAME", ".nojekyll", ".gitattributes", MANIFEST_FILE, BRANCH_NOTICE_FILE];
+
+/// Whether the file at `path`, relative to the deployed directory, should be left out of the deploy
+pub fn is_excluded(path: &Path, opts: &Options) -> bool {
+    if opts.no_sourcemaps && path.extension().is_some_and(|ext| ext == "map") {
+        return true
+    }
+    // the placeholders would be pointless if they were left out again
+    if opts.keep_empty_dirs && path.file_name().is_some_and(|name| name == PLACEHOLDER_FILE) {
+        return false
+    }
+    if !opts.only_extensions.is_empty() && !CREATED_FILES.iter().any(|file| path == Path::new(file)) {
+        let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+        let allowed = ext.is_some_and(|ext| WEB_EXTENSIONS.contains(&ext.as_str()) || opts.only_extensions.contains(&ext));
+        if !allowed {
+            return true
+        }
+    }
+    opts.exclude_hidden && !CREATED_HIDDEN_FILES.iter().any(|file| path == Path::new(file)) && is_hidden(path)
+}
+
+/// Whether `path` is a hidden file, or is inside of a hidden directory
+fn is_hidden(path: &Path) -> bool {
+    path.components().any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Gets the paths, relative to `dir` and sorted, of every file in `dir` that will be deployed
+pub fn deployed_files(dir: &Path, opts: &Options) -> Result<Vec<PathBuf>, Error> {
+    let mut files: Vec<PathBuf> = walk_files(dir)?.into_iter()
+        .filter_map(|path| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+        .filter(|path| !is_excluded(path, opts))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Copies every file in `dir` that will be deployed into `target`, keeping their relative paths
+pub fn copy_deployed_files(dir: &Path, target: &Path, opts: &Options) -> Result<(), Error> {
+    create_dir_all(target)?;
+    for path in deployed_files(dir, opts)? {
+        let destination = target.join(&path);
+        if let Some(parent) = destination.parent() {
+            create_dir_all(parent)?;
+        }
+        copy(dir.join(&path), destination)?;
+    }
+    Ok(())
+}
+
+/// Writes an empty `.gitkeep` into every empty directory in `dir`, since git doesn't track empty directories 
+/// and they would be missing from the deployed branch otherwise. Returns how many were written.
+pub fn keep_empty_dirs(dir: &Path) -> Result<usize, Error> {
+    let mut written = 0;
+    let mut is_empty = true;
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        is_empty = false;
+        if path.is_dir() && path.file_name().is_some_and(|name| name != ".git") {
+            written += keep_empty_dirs(&path)?;
+        }
+    }
+    if is_empty {
+        write(dir.join(PLACEHOLDER_FILE), "")?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Writes a manifest listing the SHA-256 of every deployed file in `dir` (except itself) to `deploy-manifest.json`, 
+/// so that consumers can verify the served files
+/// ```json
+/// {
+///   "files": [
+///     {"path": "index.html", "sha256": "..."}
+///   ]
+/// }
+/// ```
+pub fn write_manifest(dir: &Path, opts: &Options) -> Result<(), Error> {
+    let mut entries = Vec::new();
+    for path in deployed_files(dir, opts)? {
+        if path == Path::new(MANIFEST_FILE) {
+            continue
+        }
+        let hash = sha256_hex(&read(dir.join(&path))?);
+        entries.push(format!("    {{\"path\": {}, \"sha256\": \"{}\"}}", json::string(&slash_path(&path)), hash));
+    }
+    write(dir.join(MANIFEST_FILE), format!("{{\n  \"files\": [\n{}\n  ]\n}}\n", entries.join(",\n")))
+}
+
+/// Formats a relative path with `/` separators on every platform
+pub fn slash_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Keeps every excluded file in `dir` from being staged by listing it in the `.git/info/exclude` file 
+/// of the repo in `dir`, which must already be initialized.
+pub fn write_excludes(dir: &Path, opts: &Options) -> Result<(), Error> {
+    let mut excludes = String::new();
+    for path in walk_files(dir)? {
+        let path = path.strip_prefix(dir).unwrap_or(&path);
+        if is_excluded(path, opts) {
+            excludes.push_str(&exclude_pattern(path));
+            excludes.push('\n');
+        }
+    }
+    write(dir.join(".git/info/exclude"), excludes)
+}
+
+/// Turns a relative path into a gitignore pattern matching only that exact file
+fn exclude_pattern(path: &Path) -> String {
+    let mut pattern = String::from("/");
+    for c in slash_path(path).chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '!' | '#' | ' ' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// Lists every file in `dir` that will be deployed along with its size, sorted by path, 
+/// as aligned text or as JSON for `OutputFormat::Json`
+/// ```json
+/// {
+///   "files": [
+///     {"path": "index.html", "size": 1024}
+///   ]
+/// }
+/// ```
+pub fn list_deployed_files(dir: &Path, format: OutputFormat, opts: &Options) -> Result<String, Error> {
+    let mut files = Vec::new();
+    for path in deployed_files(dir, opts)? {
+        files.push((slash_path(&path), metadata(dir.join(&path))?.len()));
+    }
+
+    Ok(match format {
+        OutputFormat::Text => {
+            let total: u64 = files.iter().map(|(_, size)| size).sum();
+            let mut out: String = files.iter().map(|(path, size)| format!("{:>10}  {}\n", size, path)).collect();
+            out.push_str(&format!("{} file(s), {} bytes\n", files.len(), total));
+            out
+        }
+        OutputFormat::Json => {
+            let entries: Vec<String> = files.iter()
+                .map(|(path, size)| format!("    {{\"path\": {}, \"size\": {}}}", json::string(path), size))
+                .collect();
+            format!("{{\n  \"files\": [\n{}\n  ]\n}}\n", entries.join(",\n"))
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_extensions_leaves_out_everything_else() {
+        let opts = Options { only_extensions: vec!["png".to_string()], ..Options::default() };
+        for path in ["notes.txt", "docs/readme.TXT", "data.json", "LICENSE"] {
+            assert!(is_excluded(Path::new(path), &opts), "{} should be excluded", path);
+        }
+        for path in ["index.html", "app_bg.wasm", "app.js", "style.css", "assets/logo.PNG", "CNAME", ".nojekyll", BRANCH_NOTICE_FILE] {
+            assert!(!is_excluded(Path::new(path), &opts), "{} should be deployed", path);
+        }
+        // only the files created by trunk-ghpages at the root are kept
+        assert!(is_excluded(Path::new("docs/CNAME"), &opts));
+    }
+
+    #[test]
+    fn only_extensions_can_list_txt() {
+        assert!(!is_excluded(Path::new("notes.txt"), &Options::default()));
+        let opts = Options { only_extensions: vec!["png".to_string(), "txt".to_string()], ..Options::default() };
+        assert!(!is_excluded(Path::new("notes.txt"), &opts));
+        assert!(!is_excluded(Path::new("docs/readme.TXT"), &opts));
+        // the other exclusions still apply to the listed extensions
+        let opts = Options { only_extensions: vec!["txt".to_string()], exclude_hidden: true, ..Options::default() };
+        assert!(is_excluded(Path::new(".notes.txt"), &opts));
+    }
+
+    #[test]
+    fn keep_empty_dirs_fills_only_the_empty_dirs_once() {
+        let dir = std::env::temp_dir().join(format!("trunk-ghpages-empty-dirs-{}", std::process::id()));
+        for empty in ["assets/empty", "data/nested/empty", ".git/refs"] {
+            create_dir_all(dir.join(empty)).unwrap();
+        }
+        write(dir.join("index.html"), "").unwrap();
+        write(dir.join("data/file.json"), "{}").unwrap();
+
+        let written = keep_empty_dirs(&dir).unwrap();
+        let files = walk_files(&dir).unwrap();
+        let written_again = keep_empty_dirs(&dir).unwrap();
+        let files_again = walk_files(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut files: Vec<PathBuf> = files.iter().map(|path| path.strip_prefix(&dir).unwrap().to_path_buf()).collect();
+        files.sort();
+        assert_eq!(written, 2);
+        assert_eq!(files, [Path::new("assets/empty/.gitkeep"), Path::new("data/file.json"), Path::new("data/nested/empty/.gitkeep"), Path::new("index.html")]);
+        assert_eq!(written_again, 0);
+        assert_eq!(files_again.len(), files.len());
+    }
+
+    #[test]
+    fn deployed_files_skips_files_with_other_extensions() {
+        let dir = std::env::temp_dir().join(format!("trunk-ghpages-only-extensions-{}", std::process::id()));
+        create_dir_all(dir.join("assets")).unwrap();
+        for file in ["index.html", "notes.txt", "assets/logo.png"] {
+            write(dir.join(file), "").unwrap();
+        }
+        let opts = Options { only_extensions: vec!["png".to_string()], ..Options::default() };
+        let files = deployed_files(&dir, &opts);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files.unwrap(), [Path::new("assets/logo.png"), Path::new("index.html")]);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{fs::{read_dir, remove_dir_all, remove_file}, io::Error, path::Path, process::Command, time::Instant};
+
+use crate::{cli::Options, create_temp_dir, log, merge, push::{add_all, head_sha, install_lfs, output_step, run_step, seed_branch_readme, truncate_history, Commit}, stage};
+
+/// Creates the deploy commit of `dist` on the local `branch` of the source repo at `repo_root`, without pushing it.
+///
+/// The branch is checked out in a temporary worktree, so the commit is based on the existing history of the branch
+/// and stays in the source repo for a later `git push` after the worktree is removed again.
+/// With a `subdir`, only that directory of the branch is replaced.
+///
+/// Returns the hash of the new commit, or None if nothing changed
+///
+/// # Example
+/// ```no_run
+/// # use trunk_ghpages::worktree::commit_to_branch;
+/// # use trunk_ghpages::{cli::Options, push::Commit};
+/// # let opts = Options::default();
+/// # let commit = Commit { message: "Update gh-pages".to_string(), date: None };
+/// let cwd = std::env::current_dir()?;
+/// let sha = commit_to_branch(&cwd, &cwd.join("dist"), "gh-pages", None, &commit, &opts)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn commit_to_branch(repo_root: &Path, dist: &Path, branch: &str, subdir: Option<&str>, commit: &Commit, opts: &Options) -> Result<Option<String>, Error> {
+    let temp_dir = create_temp_dir(opts)?;
+    let worktree = temp_dir.join(branch.replace('/', "-"));
+    let staging = Instant::now();
+    let res = add_worktree(repo_root, &worktree, branch, opts)
+        .and_then(|()| stage_worktree(&worktree, dist, subdir, opts))
+        .and_then(|()| {
+            log::add_timing("staging", staging.elapsed());
+            let committing = Instant::now();
+            let sha = commit_staged(&worktree, branch, commit, opts)?;
+            log::add_timing("commit", committing.elapsed());
+            Ok(sha)
+        });
+    if worktree.exists() {
+        run_step(Command::new(opts.git_bin())
+            .current_dir(repo_root)
+            .args(["worktree","remove","--force"])
+            .arg(&worktree), opts)?;
+    }
+    remove_dir_all(&temp_dir)?;
+    res
+}
+
+/// Checks out `branch` of the repo at `repo_root` in a new worktree at `path`.
+///
+/// The local branch is used if it exists, then the remote-tracking branch of origin.
+/// Otherwise the branch is created without any history, starting out empty.
+pub fn add_worktree(repo_root: &Path, path: &Path, branch: &str, opts: &Options) -> Result<(), Error> {
+    let mut cmd = Command::new(opts.git_bin());
+    cmd.current_dir(repo_root).args(["worktree","add"]);
+    let remote_branch = format!("origin/{}", branch);
+    if ref_exists(repo_root, &format!("refs/heads/{}", branch), opts)? {
+        run_step(cmd.arg(path).arg(branch), opts)?;
+    } else if ref_exists(repo_root, &format!("refs/remotes/{}", remote_branch), opts)? {
+        run_step(cmd.args(["-b",branch]).arg(path).arg(&remote_branch), opts)?;
+    } else {
+        run_step(cmd.arg("--detach").arg(path), opts)?;
+        for args in [&["checkout","-q","--orphan",branch][..], &["rm","-rfq","."]] {
+            run_step(Command::new(opts.git_bin()).current_dir(path).args(args), opts)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the contents of the worktree at `worktree` (or only its `subdir`) with the deployed files of `dist`, and stages them
+pub fn stage_worktree(worktree: &Path, dist: &Path, subdir: Option<&str>, opts: &Options) -> Result<(), Error> {
+    let target = match subdir {
+        Some(subdir) => {
+            let target = worktree.join(subdir);
+            if target.exists() {
+                remove_dir_all(&target)?;
+            }
+            target
+        }
+        None => {
+            for entry in read_dir(worktree)? {
+                let entry = entry?;
+                match (entry.file_name() == ".git", entry.file_type()?.is_dir()) {
+                    (true, _) => {}
+                    (false, true) => remove_dir_all(entry.path())?,
+                    (false, false) => remove_file(entry.path())?,
+                }
+            }
+            worktree.to_path_buf()
+        }
+    };
+    stage::copy_deployed_files(dist, &target, opts)?;
+    install_lfs(&target, opts)?;
+    if let Some(subdir) = subdir {
+        merge::redirect_root(worktree, subdir, opts)?;
+    }
+    if opts.pages_branch_readme {
+        seed_branch_readme(worktree, ref_exists(worktree, "HEAD", opts)?.then_some("HEAD"), opts.branch(), opts)?;
+    }
+    add_all(worktree, opts)
+}
+
+/// Commits the staged changes of the worktree at `worktree`, or returns None if there are none
+fn commit_staged(worktree: &Path, branch: &str, commit: &Commit, opts: &Options) -> Result<Option<String>, Error> {
+    let unchanged = ref_exists(worktree, "HEAD", opts)? && output_step(Command::new(opts.git_bin())
+        .current_dir(worktree)
+        .args(["diff","--cached","--quiet"]))?
+        .status
+        .success();
+    if unchanged {
+        log::warn(format!("Nothing changed on branch '{}', so there is nothing to commit", branch));
+        return Ok(None)
+    }
+
+    let mut cmd = Command::new(opts.git_bin());
+    if let Some(date) = &commit.date {
+        cmd.env("GIT_AUTHOR_DATE", date).env("GIT_COMMITTER_DATE", date);
+    }
+    run_step(cmd.current_dir(worktree).args(["commit","-q","-m",&commit.message]), opts)?;
+    if let Some(max) = opts.max_history {
+        truncate_history(worktree, max.get(), opts)?;
+    }
+    head_sha(worktree, opts).map(Some)
+}
+
+/// Whether `reference` resolves to a commit in the repo at `dir`
+fn ref_exists(dir: &Path, reference: &str, opts: &Options) -> Result<bool, Error> {
+    Ok(output_step(Command::new(opts.git_bin())
+        .current_dir(dir)
+        .args(["rev-parse","--verify","-q",&format!("{}^{{commit}}", reference)]))?
+        .status
+        .success())
+}